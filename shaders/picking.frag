@@ -0,0 +1,9 @@
+#version 430 core
+
+uniform layout(location = 1) uint nodeId;
+out layout(location = 0) uint id;
+
+void main()
+{
+    id = nodeId;
+}
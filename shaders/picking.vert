@@ -0,0 +1,10 @@
+#version 430 core
+
+layout(location = 0) in vec3 position;
+
+uniform layout(location = 0) mat4 t;
+
+void main()
+{
+    gl_Position = t * vec4(position, 100.0);
+}
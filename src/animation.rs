@@ -0,0 +1,585 @@
+extern crate nalgebra_glm as glm;
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::rotation::{self, DEFAULT_EULER_ORDER};
+use crate::scene_graph::{NodeId, SceneGraph};
+
+// Keyframe animation of node transforms.
+//
+// A clip is a set of tracks, each animating one property of one node. Every keyframe stores its
+// value as a vec4: translations and scales only use xyz, while rotations are quaternions stored
+// as (x, y, z, w), just like glTF does it.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Property {
+    Translation,
+    Rotation,
+    Scale,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interpolation {
+    Step,
+    #[default]
+    Linear,
+    Cubic,
+}
+
+// What happens when a player runs past the end of its clip
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Wrap {
+    #[default]
+    Loop,
+    Clamp,
+}
+
+// The tangents are derivatives per second, and only used by cubic interpolation
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe {
+    pub time: f32,
+    pub value: glm::Vec4,
+    pub in_tangent: glm::Vec4,
+    pub out_tangent: glm::Vec4,
+}
+
+#[derive(Clone, Debug)]
+pub struct Track {
+    // Path to the animated node, relative to the node the clip is played on. A plain name that
+    // is not a direct child is looked for anywhere below it, which is how glTF targets work.
+    pub target: String,
+    pub property: Property,
+    pub interpolation: Interpolation,
+    pub keys: Vec<Keyframe>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Clip {
+    pub name: String,
+    pub duration: f32,
+    pub wrap: Wrap,
+    pub tracks: Vec<Track>,
+}
+
+impl Track {
+    // The value of the track at `time` seconds, holding the first and last keys outside them
+    pub fn sample(&self, time: f32) -> glm::Vec4 {
+        let keys = &self.keys;
+        let last = keys.len() - 1;
+        if time <= keys[0].time {
+            return keys[0].value;
+        }
+        if time >= keys[last].time {
+            return keys[last].value;
+        }
+
+        let next = keys.partition_point(|key| key.time <= time);
+        let (a, b) = (&keys[next - 1], &keys[next]);
+        let dt = b.time - a.time;
+        let u = (time - a.time) / dt;
+
+        match (self.interpolation, self.property) {
+            (Interpolation::Step, _) => a.value,
+            (Interpolation::Linear, Property::Rotation) => {
+                rotation::quat_slerp(&glm::Quat::from(a.value), &glm::Quat::from(b.value), u).coords
+            }
+            (Interpolation::Linear, _) => glm::lerp(&a.value, &b.value, u),
+            (Interpolation::Cubic, property) => {
+                // Cubic Hermite spline, see the glTF specification
+                let (u2, u3) = (u * u, u * u * u);
+                let value = (2.0 * u3 - 3.0 * u2 + 1.0) * a.value
+                    + dt * (u3 - 2.0 * u2 + u) * a.out_tangent
+                    + (-2.0 * u3 + 3.0 * u2) * b.value
+                    + dt * (u3 - u2) * b.in_tangent;
+                match property {
+                    Property::Rotation => glm::normalize(&value),
+                    _ => value,
+                }
+            }
+        }
+    }
+
+    // Fill in Catmull-Rom tangents, so cubic tracks without tangents of their own pass smoothly
+    // through every key
+    fn compute_tangents(&mut self) {
+        let n = self.keys.len();
+        for i in 0..n {
+            let before = &self.keys[i.saturating_sub(1)];
+            let after = &self.keys[usize::min(i + 1, n - 1)];
+            let tangent = if after.time > before.time {
+                (after.value - before.value) / (after.time - before.time)
+            } else {
+                glm::zero()
+            };
+            self.keys[i].in_tangent = tangent;
+            self.keys[i].out_tangent = tangent;
+        }
+    }
+}
+
+impl Clip {
+    // Map time since the clip started onto the clip's own timeline
+    pub fn local_time(&self, time: f32) -> f32 {
+        match self.wrap {
+            Wrap::Loop if self.duration > 0.0 => time.rem_euclid(self.duration),
+            Wrap::Loop => 0.0,
+            Wrap::Clamp => time.clamp(0.0, self.duration),
+        }
+    }
+
+    fn from_tracks(name: String, wrap: Wrap, tracks: Vec<Track>) -> Clip {
+        let duration = tracks
+            .iter()
+            .filter_map(|track| track.keys.last())
+            .map(|key| key.time)
+            .fold(0_f32, f32::max);
+        Clip {
+            name,
+            duration,
+            wrap,
+            tracks,
+        }
+    }
+}
+
+// Plays one clip on the subtree below a node. Targets are looked up once when the player is
+// made, so the nodes may be moved around in the graph afterwards but must not be renamed.
+#[derive(Clone, Debug)]
+pub struct AnimationPlayer {
+    pub clip: Clip,
+    pub time: f32,
+    pub speed: f32,
+    pub playing: bool,
+    targets: Vec<Option<NodeId>>,
+}
+
+impl AnimationPlayer {
+    pub fn new(clip: &Clip, scene: &SceneGraph, root: NodeId) -> AnimationPlayer {
+        let targets = clip
+            .tracks
+            .iter()
+            .map(|track| find_target(scene, root, &track.target))
+            .collect();
+        AnimationPlayer {
+            clip: clip.clone(),
+            time: 0.0,
+            speed: 1.0,
+            playing: true,
+            targets,
+        }
+    }
+
+    pub fn advance(&mut self, delta_time: f32) {
+        if self.playing {
+            self.time += delta_time * self.speed;
+        }
+    }
+
+    // Only clamped clips ever finish
+    #[allow(dead_code)]
+    pub fn finished(&self) -> bool {
+        self.clip.wrap == Wrap::Clamp && self.time >= self.clip.duration
+    }
+
+    // Every track whose node was found, along with its value at the current time
+    pub fn sample(&self) -> Vec<(NodeId, &Track, glm::Vec4)> {
+        let time = self.clip.local_time(self.time);
+        self.clip
+            .tracks
+            .iter()
+            .zip(&self.targets)
+            .filter_map(|(track, target)| target.map(|node| (node, track, track.sample(time))))
+            .collect()
+    }
+
+    // Pose the targeted nodes as they are at the current time
+    #[allow(dead_code)]
+    pub fn apply(&self, scene: &mut SceneGraph) {
+        for (node, track, value) in self.sample() {
+            set_property(scene, node, track.property, &value);
+        }
+    }
+}
+
+// Read and write node transforms in the same vec4 form as keyframes
+pub fn get_property(scene: &SceneGraph, node: NodeId, property: Property) -> glm::Vec4 {
+    match property {
+        Property::Translation => glm::vec3_to_vec4(&scene[node].position()),
+        Property::Rotation => scene[node].orientation().coords,
+        Property::Scale => glm::vec3_to_vec4(&scene[node].scale()),
+    }
+}
+
+pub fn set_property(scene: &mut SceneGraph, node: NodeId, property: Property, value: &glm::Vec4) {
+    match property {
+        Property::Translation => scene[node].set_position(value.xyz()),
+        Property::Rotation => scene[node].set_orientation(glm::Quat::from(*value)),
+        Property::Scale => scene[node].set_scale(value.xyz()),
+    }
+}
+
+fn find_target(scene: &SceneGraph, root: NodeId, target: &str) -> Option<NodeId> {
+    scene.find(root, target).or_else(|| {
+        scene
+            .query(root, &format!("**/{}", target))
+            .first()
+            .copied()
+    })
+}
+
+// == // Clips in scene files
+
+// A clip as written in a scene file. Every key is a (time, value) pair, where rotations are given
+// as (pitch, yaw, roll) Euler angles like everywhere else in the scene file. Cubic tracks get
+// Catmull-Rom tangents.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClipDescription {
+    pub name: String,
+    #[serde(default)]
+    pub wrap: Wrap,
+    pub tracks: Vec<TrackDescription>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrackDescription {
+    pub target: String,
+    pub property: Property,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    pub keys: Vec<(f32, [f32; 3])>,
+}
+
+impl ClipDescription {
+    pub fn to_clip(&self) -> Result<Clip, String> {
+        let tracks = self
+            .tracks
+            .iter()
+            .map(|track| {
+                let values = track.keys.iter().map(|(_, value)| {
+                    let value = glm::make_vec3(value);
+                    match track.property {
+                        Property::Rotation => {
+                            rotation::quat_from_euler(&value, DEFAULT_EULER_ORDER).coords
+                        }
+                        _ => glm::vec4(value.x, value.y, value.z, 0.0),
+                    }
+                });
+                let times = track.keys.iter().map(|(time, _)| *time);
+                make_track(
+                    &self.name,
+                    &track.target,
+                    track.property,
+                    track.interpolation,
+                    times.zip(values).collect(),
+                )
+            })
+            .collect::<Result<Vec<Track>, String>>()?;
+        Ok(Clip::from_tracks(self.name.clone(), self.wrap, tracks))
+    }
+}
+
+fn make_track(
+    clip: &str,
+    target: &str,
+    property: Property,
+    interpolation: Interpolation,
+    keys: Vec<(f32, glm::Vec4)>,
+) -> Result<Track, String> {
+    if keys.is_empty() {
+        return Err(format!("Track for {} in clip {} has no keys", target, clip));
+    }
+    if keys.windows(2).any(|pair| pair[1].0 < pair[0].0) {
+        return Err(format!(
+            "Keys for {} in clip {} are not sorted by time",
+            target, clip
+        ));
+    }
+
+    let mut track = Track {
+        target: target.to_string(),
+        property,
+        interpolation,
+        keys: keys
+            .into_iter()
+            .map(|(time, value)| Keyframe {
+                time,
+                value,
+                in_tangent: glm::zero(),
+                out_tangent: glm::zero(),
+            })
+            .collect(),
+    };
+    if property == Property::Rotation {
+        keep_quaternions_close(&mut track.keys);
+    }
+    if interpolation == Interpolation::Cubic {
+        track.compute_tangents();
+    }
+    Ok(track)
+}
+
+// q and -q are the same rotation, so flip keys onto the same side as the one before them to
+// make interpolation take the short way around
+fn keep_quaternions_close(keys: &mut [Keyframe]) {
+    for i in 1..keys.len() {
+        if glm::dot(&keys[i - 1].value, &keys[i].value) < 0.0 {
+            keys[i].value = -keys[i].value;
+        }
+    }
+}
+
+// == // Clips in glTF files
+
+// Load every animation in a .gltf or .glb file. Tracks target the animated glTF nodes by name,
+// so the scene nodes need the same names as the nodes in the glTF file. Values are used as they
+// are, without converting between glTF meters and scene units.
+pub fn load_gltf_clips(path: &str) -> Result<Vec<Clip>, String> {
+    let gltf =
+        gltf::Gltf::open(path).map_err(|e| format!("Failed to load glTF file {}: {}", path, e))?;
+    let directory = Path::new(path).parent().unwrap_or_else(|| Path::new("."));
+
+    let buffers = gltf
+        .buffers()
+        .map(|buffer| match buffer.source() {
+            gltf::buffer::Source::Bin => gltf
+                .blob
+                .clone()
+                .ok_or(format!("glTF file {} has no binary chunk", path)),
+            gltf::buffer::Source::Uri(uri) => std::fs::read(directory.join(uri))
+                .map_err(|e| format!("Failed to read glTF buffer {}: {}", uri, e)),
+        })
+        .collect::<Result<Vec<Vec<u8>>, String>>()?;
+
+    let mut clips = vec![];
+    for animation in gltf.animations() {
+        let name = match animation.name() {
+            Some(name) => name.to_string(),
+            None => format!("animation_{}", animation.index()),
+        };
+
+        let mut tracks = vec![];
+        for channel in animation.channels() {
+            let node = channel.target().node();
+            let target = match node.name() {
+                Some(name) => name.to_string(),
+                None => format!("node_{}", node.index()),
+            };
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()][..]));
+            let times: Vec<f32> = match reader.read_inputs() {
+                Some(inputs) => inputs.collect(),
+                None => continue,
+            };
+            let (property, values): (Property, Vec<glm::Vec4>) = match reader.read_outputs() {
+                Some(gltf::animation::util::ReadOutputs::Translations(values)) => (
+                    Property::Translation,
+                    values.map(|v| glm::vec4(v[0], v[1], v[2], 0.0)).collect(),
+                ),
+                Some(gltf::animation::util::ReadOutputs::Rotations(values)) => (
+                    Property::Rotation,
+                    values.into_f32().map(|v| glm::make_vec4(&v)).collect(),
+                ),
+                Some(gltf::animation::util::ReadOutputs::Scales(values)) => (
+                    Property::Scale,
+                    values.map(|v| glm::vec4(v[0], v[1], v[2], 0.0)).collect(),
+                ),
+                // Morph targets are not supported
+                _ => continue,
+            };
+
+            let interpolation = match channel.sampler().interpolation() {
+                gltf::animation::Interpolation::Step => Interpolation::Step,
+                gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                gltf::animation::Interpolation::CubicSpline => Interpolation::Cubic,
+            };
+            let track = if interpolation == Interpolation::Cubic {
+                // Cubic outputs come as (in tangent, value, out tangent) for every key
+                if values.len() != 3 * times.len() {
+                    return Err(format!("Malformed cubic spline for {} in {}", target, path));
+                }
+                let mut track = make_track(
+                    &name,
+                    &target,
+                    property,
+                    interpolation,
+                    times
+                        .iter()
+                        .copied()
+                        .zip(values.iter().skip(1).step_by(3).copied())
+                        .collect(),
+                )?;
+                for (key, tangents) in track.keys.iter_mut().zip(values.chunks(3)) {
+                    // Follow the key if it was flipped onto the other side
+                    let sign = glm::dot(&key.value, &tangents[1]).signum();
+                    key.in_tangent = sign * tangents[0];
+                    key.out_tangent = sign * tangents[2];
+                }
+                track
+            } else {
+                make_track(
+                    &name,
+                    &target,
+                    property,
+                    interpolation,
+                    times.into_iter().zip(values).collect(),
+                )?
+            };
+            tracks.push(track);
+        }
+        clips.push(Clip::from_tracks(name, Wrap::Loop, tracks));
+    }
+    Ok(clips)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene_graph::SceneNode;
+
+    fn track(property: Property, interpolation: Interpolation, keys: &[(f32, glm::Vec4)]) -> Track {
+        make_track("test", "node", property, interpolation, keys.to_vec()).unwrap()
+    }
+
+    fn x(value: f32) -> glm::Vec4 {
+        glm::vec4(value, 0.0, 0.0, 0.0)
+    }
+
+    fn yaw(angle: f32) -> glm::Vec4 {
+        rotation::quat_from_axis_angle(&glm::vec3(0.0, 1.0, 0.0), angle).coords
+    }
+
+    #[test]
+    fn step_holds_each_key() {
+        let track = track(
+            Property::Translation,
+            Interpolation::Step,
+            &[(0.0, x(1.0)), (1.0, x(2.0)), (2.0, x(4.0))],
+        );
+        assert_eq!(track.sample(-1.0), x(1.0));
+        assert_eq!(track.sample(0.99), x(1.0));
+        assert_eq!(track.sample(1.0), x(2.0));
+        assert_eq!(track.sample(1.5), x(2.0));
+        assert_eq!(track.sample(3.0), x(4.0));
+    }
+
+    #[test]
+    fn linear_translations_lerp_and_rotations_slerp() {
+        let translation = track(
+            Property::Translation,
+            Interpolation::Linear,
+            &[(0.0, x(0.0)), (2.0, x(4.0))],
+        );
+        assert_eq!(translation.sample(0.5), x(1.0));
+
+        // Halfway between no turn and two radians is one radian, not a shorter lerped quaternion
+        let rotation = track(
+            Property::Rotation,
+            Interpolation::Linear,
+            &[(0.0, yaw(0.0)), (1.0, yaw(2.0))],
+        );
+        assert!(glm::distance(&rotation.sample(0.5), &yaw(1.0)) < 1e-6);
+        assert!((glm::length(&rotation.sample(0.3)) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn rotations_take_the_short_way() {
+        // The same rotation with the other sign must not turn the long way around
+        let rotation = track(
+            Property::Rotation,
+            Interpolation::Linear,
+            &[(0.0, yaw(0.0)), (1.0, -yaw(0.5))],
+        );
+        let halfway = rotation.sample(0.5);
+        assert!(
+            glm::distance(&halfway, &yaw(0.25)).min(glm::distance(&halfway, &-yaw(0.25))) < 1e-6
+        );
+    }
+
+    #[test]
+    fn cubic_hermite() {
+        // Without tangents the curve eases in and out like smoothstep
+        let mut track = track(
+            Property::Translation,
+            Interpolation::Linear,
+            &[(0.0, x(0.0)), (1.0, x(1.0))],
+        );
+        track.interpolation = Interpolation::Cubic;
+        assert!((track.sample(0.25).x - 0.15625).abs() < 1e-6);
+        assert!((track.sample(0.5).x - 0.5).abs() < 1e-6);
+
+        // Tangents are per second, so two seconds apart a slope of a half makes a straight line
+        track.keys[0].time = -1.0;
+        track.keys[0].out_tangent = x(0.5);
+        track.keys[1].in_tangent = x(0.5);
+        assert!((track.sample(-0.5).x - 0.25).abs() < 1e-6);
+        assert!((track.sample(0.0).x - 0.5).abs() < 1e-6);
+
+        // Catmull-Rom tangents through keys on a line keep to the line, and pass through every
+        // key
+        let track = self::track(
+            Property::Translation,
+            Interpolation::Cubic,
+            &[(0.0, x(0.0)), (1.0, x(2.0)), (2.0, x(4.0)), (3.0, x(1.0))],
+        );
+        assert!((track.sample(0.5).x - 1.0).abs() < 1e-6);
+        for (time, value) in [(1.0, 2.0), (2.0, 4.0), (3.0, 1.0)] {
+            assert_eq!(track.sample(time), x(value));
+        }
+    }
+
+    fn clip(wrap: Wrap) -> Clip {
+        let tracks = vec![track(
+            Property::Translation,
+            Interpolation::Linear,
+            &[(0.0, x(0.0)), (2.0, x(2.0))],
+        )];
+        Clip::from_tracks("move".to_string(), wrap, tracks)
+    }
+
+    #[test]
+    fn loop_and_clamp() {
+        let (looping, clamped) = (clip(Wrap::Loop), clip(Wrap::Clamp));
+        assert_eq!(looping.duration, 2.0);
+        assert_eq!(looping.local_time(2.5), 0.5);
+        assert_eq!(looping.local_time(-0.5), 1.5);
+        assert_eq!(clamped.local_time(2.5), 2.0);
+        assert_eq!(clamped.local_time(-0.5), 0.0);
+    }
+
+    #[test]
+    fn player_advances_and_poses_the_target() {
+        let mut scene = SceneGraph::new();
+        let root = scene.add(SceneNode::new().with_name("root"));
+        let body = scene.add(SceneNode::new().with_name("body"));
+        let node = scene.add(SceneNode::new().with_name("node"));
+        scene.add_child(root, body);
+        scene.add_child(body, node);
+
+        // The target is not a direct child, so it is looked for further down
+        let mut player = AnimationPlayer::new(&clip(Wrap::Clamp), &scene, root);
+        player.speed = 2.0;
+        player.advance(0.25);
+        assert_eq!(player.time, 0.5);
+        player.apply(&mut scene);
+        assert_eq!(scene[node].position(), glm::vec3(0.5, 0.0, 0.0));
+
+        player.playing = false;
+        player.advance(10.0);
+        assert_eq!(player.time, 0.5);
+        assert!(!player.finished());
+
+        player.playing = true;
+        player.advance(10.0);
+        assert!(player.finished());
+        player.apply(&mut scene);
+        assert_eq!(scene[node].position(), glm::vec3(2.0, 0.0, 0.0));
+
+        // Looping clips keep going around instead
+        let mut player = AnimationPlayer::new(&clip(Wrap::Loop), &scene, root);
+        player.advance(2.5);
+        assert!(!player.finished());
+        player.apply(&mut scene);
+        assert_eq!(scene[node].position(), glm::vec3(0.5, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,377 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::HashMap;
+
+use crate::animation::{self, AnimationPlayer, Property};
+use crate::rotation;
+use crate::scene_graph::{NodeId, SceneGraph};
+
+// Blending several animation clips on one hierarchy.
+//
+// An `Animator` evaluates a stack of layers from the bottom up. Every layer plays one or more
+// clips with their own weights, and is then mixed into the result of the layers below it:
+// override layers blend towards their own pose by the layer weight, while additive layers add
+// how far their clips have moved away from their first keyframe. Nodes that no clip touches
+// keep the pose they had when the animator first saw them animated.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Blend {
+    Override,
+    Additive,
+}
+
+#[derive(Clone, Debug)]
+struct Crossfade {
+    elapsed: f32,
+    duration: f32,
+    // Weights of the players that are fading out, as they were when the fade started
+    from_weights: Vec<f32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct AnimationLayer {
+    pub name: String,
+    pub blend: Blend,
+    pub weight: f32,
+    mask: Option<Vec<NodeId>>,
+    players: Vec<(AnimationPlayer, f32)>,
+    crossfade: Option<Crossfade>,
+}
+
+impl AnimationLayer {
+    pub fn new(name: &str, blend: Blend) -> AnimationLayer {
+        AnimationLayer {
+            name: name.to_string(),
+            blend,
+            weight: 1.0,
+            mask: None,
+            players: vec![],
+            crossfade: None,
+        }
+    }
+
+    // Only let the layer animate these nodes. `SceneGraph::query(node, "**")` gives a node and
+    // everything below it.
+    pub fn with_mask(mut self, nodes: Vec<NodeId>) -> AnimationLayer {
+        self.mask = Some(nodes);
+        self
+    }
+
+    // Blend in another clip with the given weight. Weights are relative to the other clips in
+    // the layer, except that nodes with a total weight below one are only partly animated.
+    pub fn add(&mut self, player: AnimationPlayer, weight: f32) {
+        self.players.push((player, weight));
+    }
+
+    // Stop everything else in the layer and play only this clip
+    pub fn play(&mut self, player: AnimationPlayer) {
+        self.crossfade = None;
+        self.players = vec![(player, 1.0)];
+    }
+
+    // Fade out everything in the layer while fading in this clip over `duration` seconds
+    pub fn crossfade(&mut self, player: AnimationPlayer, duration: f32) {
+        if duration <= 0.0 {
+            return self.play(player);
+        }
+        self.crossfade = Some(Crossfade {
+            elapsed: 0.0,
+            duration,
+            from_weights: self.players.iter().map(|(_, weight)| *weight).collect(),
+        });
+        self.players.push((player, 0.0));
+    }
+
+    // The clip most recently started with `play` or `crossfade`
+    pub fn current_clip(&self) -> Option<&str> {
+        self.players
+            .last()
+            .map(|(player, _)| player.clip.name.as_str())
+    }
+
+    // Play every clip in the layer at this speed
+    pub fn set_speed(&mut self, speed: f32) {
+        for (player, _) in self.players.iter_mut() {
+            player.speed = speed;
+        }
+    }
+
+    pub fn advance(&mut self, delta_time: f32) {
+        for (player, _) in self.players.iter_mut() {
+            player.advance(delta_time);
+        }
+
+        if let Some(fade) = &mut self.crossfade {
+            fade.elapsed += delta_time;
+            let progress = f32::min(fade.elapsed / fade.duration, 1.0);
+            let incoming = self.players.len() - 1;
+            for (i, from_weight) in fade.from_weights.iter().enumerate() {
+                self.players[i].1 = from_weight * (1.0 - progress);
+            }
+            self.players[incoming].1 = progress;
+
+            if progress >= 1.0 {
+                self.players.drain(..incoming);
+                self.crossfade = None;
+            }
+        }
+    }
+
+    // Weighted average of every clip in the layer, along with the total weight per property.
+    // Additive layers average the offsets from each clip's first key instead of the values.
+    fn sample(&self) -> HashMap<(NodeId, Property), (glm::Vec4, f32)> {
+        let mut pose: HashMap<(NodeId, Property), (glm::Vec4, f32)> = HashMap::new();
+        for (player, weight) in &self.players {
+            if *weight <= 0.0 {
+                continue;
+            }
+            for (node, track, value) in player.sample() {
+                if self.mask.as_ref().is_some_and(|mask| !mask.contains(&node)) {
+                    continue;
+                }
+                let value = match self.blend {
+                    Blend::Override => value,
+                    Blend::Additive => difference(track.property, &track.keys[0].value, &value),
+                };
+                pose.entry((node, track.property))
+                    .and_modify(|(average, total)| {
+                        *total += weight;
+                        *average = mix(track.property, average, &value, weight / *total);
+                    })
+                    .or_insert((value, *weight));
+            }
+        }
+        pose
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Animator {
+    pub layers: Vec<AnimationLayer>,
+    rest_pose: HashMap<(NodeId, Property), glm::Vec4>,
+}
+
+impl Animator {
+    pub fn new(layers: Vec<AnimationLayer>) -> Animator {
+        Animator {
+            layers,
+            rest_pose: HashMap::new(),
+        }
+    }
+
+    pub fn layer_mut(&mut self, name: &str) -> Option<&mut AnimationLayer> {
+        self.layers.iter_mut().find(|layer| layer.name == name)
+    }
+
+    pub fn advance(&mut self, delta_time: f32) {
+        for layer in self.layers.iter_mut() {
+            layer.advance(delta_time);
+        }
+    }
+
+    // Evaluate all layers and write the blended pose into the scene
+    pub fn apply(&mut self, scene: &mut SceneGraph) {
+        let rest_pose = &mut self.rest_pose;
+        let mut pose: HashMap<(NodeId, Property), glm::Vec4> = HashMap::new();
+        for layer in &self.layers {
+            for ((node, property), (value, total_weight)) in layer.sample() {
+                let below = *pose.entry((node, property)).or_insert_with(|| {
+                    *rest_pose
+                        .entry((node, property))
+                        .or_insert_with(|| animation::get_property(scene, node, property))
+                });
+                let weight = layer.weight * f32::min(total_weight, 1.0);
+                let blended = match layer.blend {
+                    Blend::Override => mix(property, &below, &value, weight),
+                    Blend::Additive => add(property, &below, &value, weight),
+                };
+                pose.insert((node, property), blended);
+            }
+        }
+
+        for ((node, property), value) in pose {
+            animation::set_property(scene, node, property, &value);
+        }
+    }
+}
+
+// Interpolate between two values of a property
+fn mix(property: Property, a: &glm::Vec4, b: &glm::Vec4, t: f32) -> glm::Vec4 {
+    match property {
+        Property::Rotation => {
+            rotation::quat_slerp(&glm::Quat::from(*a), &glm::Quat::from(*b), t).coords
+        }
+        _ => glm::lerp(a, b, t),
+    }
+}
+
+// What has to be added to `reference` to get `value`, in the sense of `add`
+fn difference(property: Property, reference: &glm::Vec4, value: &glm::Vec4) -> glm::Vec4 {
+    match property {
+        Property::Translation => value - reference,
+        Property::Rotation => {
+            (glm::quat_inverse(&glm::Quat::from(*reference)) * glm::Quat::from(*value)).coords
+        }
+        Property::Scale => value.zip_map(reference, |v, r| if r != 0.0 { v / r } else { 1.0 }),
+    }
+}
+
+// Apply `weight` of an additive offset on top of `base`. Rotations are applied in the node's
+// own frame, after whatever rotation is below.
+fn add(property: Property, base: &glm::Vec4, offset: &glm::Vec4, weight: f32) -> glm::Vec4 {
+    match property {
+        Property::Translation => base + offset * weight,
+        Property::Rotation => {
+            let offset =
+                rotation::quat_slerp(&glm::quat_identity(), &glm::Quat::from(*offset), weight);
+            (glm::Quat::from(*base) * offset).coords
+        }
+        Property::Scale => {
+            base.component_mul(&glm::lerp(&glm::vec4(1.0, 1.0, 1.0, 1.0), offset, weight))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::{ClipDescription, Interpolation, TrackDescription, Wrap};
+    use crate::scene_graph::SceneNode;
+
+    // Keys of (time, position)
+    type Keys<'a> = &'a [(f32, [f32; 3])];
+
+    // A clip moving the named nodes along linear keys
+    fn clip(name: &str, tracks: &[(&str, Keys)]) -> animation::Clip {
+        ClipDescription {
+            name: name.to_string(),
+            wrap: Wrap::Clamp,
+            tracks: tracks
+                .iter()
+                .map(|(target, keys)| TrackDescription {
+                    target: target.to_string(),
+                    property: Property::Translation,
+                    interpolation: Interpolation::Linear,
+                    keys: keys.to_vec(),
+                })
+                .collect(),
+        }
+        .to_clip()
+        .unwrap()
+    }
+
+    // A root with two children, `a` and `b`, both resting at y = 2
+    fn scene() -> (SceneGraph, NodeId, NodeId, NodeId) {
+        let mut scene = SceneGraph::new();
+        let root = scene.add(SceneNode::new().with_name("root"));
+        let mut child = |name| {
+            let node = scene.add(SceneNode::new().with_name(name));
+            scene[node].set_position(glm::vec3(0.0, 2.0, 0.0));
+            scene.add_child(root, node);
+            node
+        };
+        let (a, b) = (child("a"), child("b"));
+        (scene, root, a, b)
+    }
+
+    fn player(clip: &animation::Clip, scene: &SceneGraph, root: NodeId) -> AnimationPlayer {
+        AnimationPlayer::new(clip, scene, root)
+    }
+
+    #[test]
+    fn crossfade_weights_over_time() {
+        let (mut scene, root, a, _) = scene();
+        let from = clip("from", &[("a", &[(0.0, [0.0, 0.0, 0.0])])]);
+        let to = clip("to", &[("a", &[(0.0, [4.0, 0.0, 0.0])])]);
+        let mut layer = AnimationLayer::new("base", Blend::Override);
+        layer.play(player(&from, &scene, root));
+        layer.crossfade(player(&to, &scene, root), 1.0);
+        assert_eq!(layer.current_clip(), Some("to"));
+
+        let mut animator = Animator::new(vec![layer]);
+        let weights = |animator: &Animator| -> Vec<f32> {
+            animator.layers[0]
+                .players
+                .iter()
+                .map(|(_, weight)| *weight)
+                .collect()
+        };
+        animator.apply(&mut scene);
+        assert_eq!(weights(&animator), [1.0, 0.0]);
+        assert_eq!(scene[a].position(), glm::vec3(0.0, 0.0, 0.0));
+
+        animator.advance(0.25);
+        assert_eq!(weights(&animator), [0.75, 0.25]);
+        animator.apply(&mut scene);
+        assert_eq!(scene[a].position(), glm::vec3(1.0, 0.0, 0.0));
+
+        // Once the fade is over only the new clip is left
+        animator.advance(1.0);
+        assert_eq!(weights(&animator), [1.0]);
+        animator.apply(&mut scene);
+        assert_eq!(scene[a].position(), glm::vec3(4.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn additive_layers_offset_the_rest_pose() {
+        let (mut scene, root, a, _) = scene();
+        // Moves one along x over a second, from wherever the node is
+        let sway = clip(
+            "sway",
+            &[("a", &[(0.0, [5.0, 5.0, 5.0]), (1.0, [6.0, 5.0, 5.0])])],
+        );
+        let mut layer = AnimationLayer::new("sway", Blend::Additive);
+        layer.play(player(&sway, &scene, root));
+        let mut animator = Animator::new(vec![layer]);
+
+        animator.advance(0.5);
+        animator.apply(&mut scene);
+        assert_eq!(scene[a].position(), glm::vec3(0.5, 2.0, 0.0));
+        // The offset is added to the rest pose every time, not to the last result
+        animator.apply(&mut scene);
+        assert_eq!(scene[a].position(), glm::vec3(0.5, 2.0, 0.0));
+
+        animator.layers[0].weight = 0.25;
+        animator.advance(0.5);
+        animator.apply(&mut scene);
+        assert_eq!(scene[a].position(), glm::vec3(0.25, 2.0, 0.0));
+    }
+
+    #[test]
+    fn additive_on_top_of_override() {
+        let (mut scene, root, a, _) = scene();
+        let base = clip("base", &[("a", &[(0.0, [0.0, 1.0, 0.0])])]);
+        let lift = clip(
+            "lift",
+            &[("a", &[(0.0, [0.0, 0.0, 0.0]), (1.0, [0.0, 2.0, 0.0])])],
+        );
+        let mut base_layer = AnimationLayer::new("base", Blend::Override);
+        base_layer.play(player(&base, &scene, root));
+        let mut lift_layer = AnimationLayer::new("lift", Blend::Additive);
+        lift_layer.play(player(&lift, &scene, root));
+        let mut animator = Animator::new(vec![base_layer, lift_layer]);
+
+        animator.advance(0.25);
+        animator.apply(&mut scene);
+        assert_eq!(scene[a].position(), glm::vec3(0.0, 1.5, 0.0));
+    }
+
+    #[test]
+    fn masks_limit_the_nodes() {
+        let (mut scene, root, a, b) = scene();
+        let both = clip(
+            "both",
+            &[
+                ("a", &[(0.0, [1.0, 0.0, 0.0])]),
+                ("b", &[(0.0, [1.0, 0.0, 0.0])]),
+            ],
+        );
+        let mut layer = AnimationLayer::new("masked", Blend::Override).with_mask(vec![a]);
+        layer.play(player(&both, &scene, root));
+        let mut animator = Animator::new(vec![layer]);
+
+        animator.apply(&mut scene);
+        assert_eq!(scene[a].position(), glm::vec3(1.0, 0.0, 0.0));
+        assert_eq!(scene[b].position(), glm::vec3(0.0, 2.0, 0.0));
+    }
+}
@@ -0,0 +1,670 @@
+extern crate nalgebra_glm as glm;
+
+use crate::input::Actions;
+use crate::rotation;
+use crate::scene_graph::{NodeId, SceneGraph};
+
+// The camera the scene is drawn through.
+//
+// Whatever the mode, the camera ends up with a pose: a position and an orientation looking down
+// its local -Z axis, like any OpenGL camera. The view matrix is the inverse of that pose. When
+// the mode changes, the pose eases from where the camera was over to where the new mode puts it.
+//
+// The projection used to be `glm::perspective(aspect, 81, ...)`. That is 81 radians, which
+// wraps around to a mirrored projection of about 39 degrees, and was turned back the right way
+// up by `mirror_flip` (a half turn around Z) at the end of the view matrix. The two cancel out,
+// so the camera uses the plain projection they add up to. None of the projections below mirror
+// the image, so triangles keep their counter-clockwise winding on screen and culling works the
+// same with all of them.
+
+// The field of view everything used to be drawn with, in radians
+pub const DEFAULT_FOV: f32 = 0.681_409;
+
+// How long it takes to ease from one mode to another, in seconds
+const TRANSITION_TIME: f32 = 0.8;
+
+// How much one step of the scroll wheel zooms, as a factor on the distance or field of view
+const ZOOM_PER_STEP: f32 = 0.9;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CameraMode {
+    // Flown around freely with the keyboard
+    Fly,
+    // Circling around a node, always looking at it
+    Orbit(NodeId),
+    // Following behind a node, looking at it
+    Chase(NodeId),
+    // Sitting inside a node, looking the way it does
+    Cockpit(NodeId),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Projection {
+    // Perspective with the camera's field of view and near and far planes
+    Perspective,
+    // Orthographic, looking straight down on the camera from above, with north being the way
+    // the camera faces
+    TopDown,
+    // Perspective without a far plane. Depth is reversed, running from 1 at the near plane to 0
+    // infinitely far away. That only spreads the precision more evenly in a float depth buffer,
+    // like the headless one. The window's depth buffer is 24-bit fixed point, where this is no
+    // better than any other infinite projection.
+    ReversedInfinite,
+}
+
+impl Projection {
+    pub const ALL: [Projection; 3] = [
+        Projection::Perspective,
+        Projection::TopDown,
+        Projection::ReversedInfinite,
+    ];
+
+    pub fn parse(name: &str) -> Option<Projection> {
+        match name {
+            "perspective" => Some(Projection::Perspective),
+            "top_down" => Some(Projection::TopDown),
+            "reversed_infinite" => Some(Projection::ReversedInfinite),
+            _ => None,
+        }
+    }
+
+    // Set up the depth test for drawing with this projection. Reversed depth needs clip space
+    // depth to run from 0 to 1 like in Direct3D, and everything else the OpenGL default of -1 to
+    // 1. Remember to clear the depth buffer afterwards, since the value it is cleared to changes.
+    pub unsafe fn set_depth_state(self) {
+        match self {
+            Projection::ReversedInfinite => {
+                gl::ClipControl(gl::LOWER_LEFT, gl::ZERO_TO_ONE);
+                gl::DepthFunc(gl::GREATER);
+                gl::ClearDepth(0.0);
+            }
+            _ => {
+                gl::ClipControl(gl::LOWER_LEFT, gl::NEGATIVE_ONE_TO_ONE);
+                gl::DepthFunc(gl::LESS);
+                gl::ClearDepth(1.0);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pose {
+    pub position: glm::Vec3,
+    pub orientation: glm::Quat,
+}
+
+impl Pose {
+    fn mix(&self, other: &Pose, t: f32) -> Pose {
+        Pose {
+            position: glm::lerp(&self.position, &other.position, t),
+            orientation: rotation::quat_slerp(&self.orientation, &other.orientation, t),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Transition {
+    from: Pose,
+    elapsed: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Camera {
+    mode: CameraMode,
+
+    // Free flying. The camera sits `fly_offset` behind and above the point being flown around,
+    // in its own frame, so turning swings it around that point.
+    pub position: glm::Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub fly_offset: glm::Vec3,
+
+    // Orbiting, with the angles measured from behind the target
+    pub orbit_yaw: f32,
+    pub orbit_pitch: f32,
+    pub orbit_distance: f32,
+
+    // Chasing, with the distance behind and height above the target, and how quickly the
+    // camera catches up with where it should be
+    pub chase_distance: f32,
+    pub chase_height: f32,
+    pub chase_stiffness: f32,
+    chase_position: Option<glm::Vec3>,
+
+    // Where the eye is inside the node in cockpit mode, in the node's own space
+    pub cockpit_offset: glm::Vec3,
+
+    pub projection: Projection,
+    pub fov: f32,
+    pub aspect: f32,
+    pub near: f32,
+    pub far: f32,
+    // How much of the world the top-down projection shows from the top of the screen to the
+    // bottom. The camera looks down from half the far plane above the eye.
+    pub top_down_height: f32,
+
+    pose: Pose,
+    transition: Option<Transition>,
+}
+
+impl Camera {
+    // A free flying camera at `position`, looking down -Z
+    pub fn new(position: glm::Vec3, aspect: f32) -> Camera {
+        let mut camera = Camera {
+            mode: CameraMode::Fly,
+            position,
+            yaw: 0_f32,
+            pitch: 0_f32,
+            fly_offset: glm::vec3(0_f32, 0.1_f32, 0.5_f32),
+            orbit_yaw: 0_f32,
+            orbit_pitch: 0.3_f32,
+            orbit_distance: 0.6_f32,
+            chase_distance: 0.4_f32,
+            chase_height: 0.12_f32,
+            chase_stiffness: 4_f32,
+            chase_position: None,
+            cockpit_offset: glm::vec3(0_f32, 0.025_f32, -0.02_f32),
+            projection: Projection::Perspective,
+            fov: DEFAULT_FOV,
+            aspect,
+            near: 0.1_f32,
+            far: 100_f32,
+            top_down_height: 4_f32,
+            pose: Pose {
+                position,
+                orientation: glm::quat_identity(),
+            },
+            transition: None,
+        };
+        camera.pose = camera.fly_pose();
+        camera
+    }
+
+    pub fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    // Switch to another mode, easing over from the current pose
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        if mode == self.mode {
+            return;
+        }
+        self.mode = mode;
+        self.transition = Some(Transition {
+            from: self.pose,
+            elapsed: 0_f32,
+        });
+
+        match mode {
+            // Carry on flying from wherever the camera is now
+            CameraMode::Fly => {
+                let forward =
+                    glm::quat_rotate_vec3(&self.pose.orientation, &glm::vec3(0_f32, 0_f32, -1_f32));
+                self.pitch = forward.y.clamp(-1_f32, 1_f32).asin();
+                self.yaw = (-forward.x).atan2(-forward.z);
+                self.position = self.pose.position
+                    - glm::quat_rotate_vec3(&self.fly_rotation(), &self.fly_offset);
+            }
+            CameraMode::Chase(_) => self.chase_position = Some(self.pose.position),
+            _ => {}
+        }
+    }
+
+    // Start flying from somewhere else, easing over from the current pose
+    pub fn fly_to(&mut self, position: glm::Vec3, yaw: f32, pitch: f32) {
+        self.mode = CameraMode::Fly;
+        self.transition = Some(Transition {
+            from: self.pose,
+            elapsed: 0_f32,
+        });
+        self.position = position;
+        self.yaw = yaw;
+        self.pitch = pitch;
+    }
+
+    // Fly in world space, in fly mode only
+    pub fn translate(&mut self, movement: &glm::Vec3) {
+        if self.mode == CameraMode::Fly {
+            self.position += movement;
+        }
+    }
+
+    // Turn the camera, or swing it around the target when orbiting. Positive yaw turns left
+    // and positive pitch looks up.
+    pub fn turn(&mut self, yaw: f32, pitch: f32) {
+        let limit = glm::half_pi::<f32>();
+        match self.mode {
+            CameraMode::Fly => {
+                self.yaw += yaw;
+                self.pitch = (self.pitch + pitch).clamp(-limit, limit);
+            }
+            CameraMode::Orbit(_) => {
+                self.orbit_yaw += yaw;
+                self.orbit_pitch = (self.orbit_pitch - pitch).clamp(-limit * 0.99, limit * 0.99);
+            }
+            _ => {}
+        }
+    }
+
+    // Zoom in by some steps of the scroll wheel, or out for negative steps. Orbiting moves the
+    // camera closer to the target, the top-down projection shows less of the world, and
+    // otherwise the field of view narrows.
+    pub fn zoom(&mut self, steps: f32) {
+        let factor = ZOOM_PER_STEP.powf(steps);
+        match self.mode {
+            _ if self.projection == Projection::TopDown => {
+                self.top_down_height = (self.top_down_height * factor).clamp(0.1_f32, 100_f32)
+            }
+            CameraMode::Orbit(_) => {
+                self.orbit_distance = (self.orbit_distance * factor).clamp(0.1_f32, 20_f32)
+            }
+            _ => self.fov = (self.fov * factor).clamp(0.1_f32, 2_f32),
+        }
+    }
+
+    // Fly and turn with the keyboard, look around with the mouse if `looking`, and zoom with the
+    // scroll wheel
+    pub fn update_from_actions(
+        &mut self,
+        actions: &Actions,
+        mouse_look: &mut MouseLook,
+        looking: bool,
+        delta_time: f32,
+    ) {
+        let rotation_speed = 0.8_f32;
+        let movement_speed = 2_f32;
+
+        let movement = glm::vec3(
+            actions.axis("move_right", "move_left"),
+            actions.axis("move_up", "move_down"),
+            actions.axis("move_forward", "move_back"),
+        );
+        self.translate(
+            &(delta_time
+                * movement_speed
+                * (movement.x * self.right()
+                    + movement.y * glm::vec3(0_f32, 1_f32, 0_f32)
+                    + movement.z * self.forward())),
+        );
+        self.turn(
+            delta_time * rotation_speed * actions.axis("yaw_left", "yaw_right"),
+            delta_time * rotation_speed * actions.axis("pitch_up", "pitch_down"),
+        );
+
+        let look = if looking {
+            (actions.value("look_x"), actions.value("look_y"))
+        } else {
+            (0.0, 0.0)
+        };
+        let (yaw, pitch) = mouse_look.look(look, delta_time);
+        self.turn(yaw, pitch);
+        let zoom = actions.value("zoom");
+        if zoom != 0.0 {
+            self.zoom(zoom);
+        }
+    }
+
+    // The way the camera looks in fly mode, and its right hand side
+    pub fn forward(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.fly_rotation(), &glm::vec3(0_f32, 0_f32, -1_f32))
+    }
+
+    pub fn right(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.fly_rotation(), &glm::vec3(1_f32, 0_f32, 0_f32))
+    }
+
+    // Move the camera to where its mode puts it. Call after the scene has been posed for the
+    // frame.
+    pub fn update(&mut self, scene: &SceneGraph, delta_time: f32) {
+        let pose = match self.mode {
+            CameraMode::Fly => self.fly_pose(),
+            CameraMode::Orbit(target) => self.orbit_pose(scene, target),
+            CameraMode::Chase(target) => self.chase_pose(scene, target, delta_time),
+            CameraMode::Cockpit(target) => self.cockpit_pose(scene, target),
+        };
+
+        self.pose = match &mut self.transition {
+            Some(transition) => {
+                transition.elapsed += delta_time;
+                let t = f32::min(transition.elapsed / TRANSITION_TIME, 1_f32);
+                let from = transition.from;
+                if t >= 1_f32 {
+                    self.transition = None;
+                }
+                from.mix(&pose, t * t * (3_f32 - 2_f32 * t))
+            }
+            None => pose,
+        };
+    }
+
+    // Push the camera up if its eye is lower than `height`
+    pub fn keep_above(&mut self, height: f32) {
+        let depth = height - self.pose.position.y;
+        if depth > 0_f32 {
+            self.pose.position.y += depth;
+            if self.mode == CameraMode::Fly {
+                self.position.y += depth;
+            }
+        }
+    }
+
+    // Where the eye is in world space
+    pub fn eye(&self) -> glm::Vec3 {
+        self.pose.position
+    }
+
+    #[allow(dead_code)]
+    pub fn pose(&self) -> Pose {
+        self.pose
+    }
+
+    pub fn view(&self) -> glm::Mat4 {
+        match self.projection {
+            Projection::TopDown => {
+                // Face the way the camera does, or north when it looks straight up or down
+                let forward =
+                    glm::quat_rotate_vec3(&self.pose.orientation, &glm::vec3(0_f32, 0_f32, -1_f32));
+                let mut north = glm::vec3(forward.x, 0_f32, forward.z);
+                if glm::length(&north) < 1e-4 {
+                    north = glm::vec3(0_f32, 0_f32, -1_f32);
+                }
+                let above = self.pose.position + glm::vec3(0_f32, self.far / 2_f32, 0_f32);
+                glm::look_at(&above, &self.pose.position, &glm::normalize(&north))
+            }
+            _ => {
+                glm::quat_to_mat4(&glm::quat_inverse(&self.pose.orientation))
+                    * glm::translate(&glm::identity(), &-self.pose.position)
+            }
+        }
+    }
+
+    pub fn projection_matrix(&self) -> glm::Mat4 {
+        match self.projection {
+            Projection::Perspective => glm::perspective(self.aspect, self.fov, self.near, self.far),
+            Projection::TopDown => {
+                let top = self.top_down_height / 2_f32;
+                let right = top * self.aspect;
+                glm::ortho(-right, right, -top, top, self.near, self.far)
+            }
+            Projection::ReversedInfinite => {
+                // Clip space depth is the near plane distance, and w the distance to the eye,
+                // so depth ends up as near / distance
+                let f = 1_f32 / (self.fov / 2_f32).tan();
+                let mut projection = glm::Mat4::zeros();
+                projection[(0, 0)] = f / self.aspect;
+                projection[(1, 1)] = f;
+                projection[(2, 3)] = self.near;
+                projection[(3, 2)] = -1_f32;
+                projection
+            }
+        }
+    }
+
+    pub fn view_projection(&self) -> glm::Mat4 {
+        self.projection_matrix() * self.view()
+    }
+
+    // Handle the console commands for the projection, or return None for any other command:
+    //     projection <perspective|top_down|reversed_infinite>
+    //     fov <degrees>          the vertical field of view of the perspective projections
+    //     clip <near> <far>      the near and far planes
+    pub fn run_command(&mut self, line: &str) -> Option<Result<String, String>> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let numbers = |words: &[&str]| {
+            words
+                .iter()
+                .map(|word| {
+                    word.parse::<f32>()
+                        .ok()
+                        .filter(|x| x.is_finite() && *x > 0.0)
+                })
+                .collect::<Option<Vec<f32>>>()
+        };
+        let result = match words.as_slice() {
+            ["projection", name] => match Projection::parse(name) {
+                Some(projection) => {
+                    self.projection = projection;
+                    Ok(format!("Projection: {:?}", projection))
+                }
+                None => Err(format!("There is no projection named {}", name)),
+            },
+            ["fov", degrees] => match numbers(&[degrees]).as_deref() {
+                Some(&[degrees]) if degrees < 180.0 => {
+                    self.fov = degrees.to_radians();
+                    Ok(format!("Field of view: {} degrees", degrees))
+                }
+                _ => Err("Usage: fov <degrees>".to_string()),
+            },
+            ["clip", near, far] => match numbers(&[near, far]).as_deref() {
+                Some(&[near, far]) if near < far => {
+                    self.near = near;
+                    self.far = far;
+                    Ok(format!("Near plane: {}, far plane: {}", near, far))
+                }
+                _ => Err("Usage: clip <near> <far>".to_string()),
+            },
+            ["projection", ..] => {
+                Err("Usage: projection <perspective|top_down|reversed_infinite>".to_string())
+            }
+            ["fov", ..] => Err("Usage: fov <degrees>".to_string()),
+            ["clip", ..] => Err("Usage: clip <near> <far>".to_string()),
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    fn fly_rotation(&self) -> glm::Quat {
+        glm::quat_angle_axis(self.yaw, &glm::vec3(0_f32, 1_f32, 0_f32))
+            * glm::quat_angle_axis(self.pitch, &glm::vec3(1_f32, 0_f32, 0_f32))
+    }
+
+    fn fly_pose(&self) -> Pose {
+        let orientation = self.fly_rotation();
+        Pose {
+            position: self.position + glm::quat_rotate_vec3(&orientation, &self.fly_offset),
+            orientation,
+        }
+    }
+
+    fn orbit_pose(&self, scene: &SceneGraph, target: NodeId) -> Pose {
+        let orientation = glm::quat_angle_axis(self.orbit_yaw, &glm::vec3(0_f32, 1_f32, 0_f32))
+            * glm::quat_angle_axis(-self.orbit_pitch, &glm::vec3(1_f32, 0_f32, 0_f32));
+        Pose {
+            position: scene.world_position(target)
+                + glm::quat_rotate_vec3(
+                    &orientation,
+                    &glm::vec3(0_f32, 0_f32, self.orbit_distance),
+                ),
+            orientation,
+        }
+    }
+
+    fn chase_pose(&mut self, scene: &SceneGraph, target: NodeId, delta_time: f32) -> Pose {
+        let world_matrix = scene.world_matrix(target);
+        let target_position = scene.world_position(target);
+
+        // Stay behind the target as seen from above, so the camera does not tumble with it
+        let behind = (world_matrix * glm::vec4(0_f32, 0_f32, 1_f32, 0_f32)).xyz();
+        let behind = glm::vec3(behind.x, 0_f32, behind.z);
+        let behind = if glm::length(&behind) > 1e-6 {
+            glm::normalize(&behind)
+        } else {
+            glm::vec3(0_f32, 0_f32, 1_f32)
+        };
+        let wanted = target_position
+            + behind * self.chase_distance
+            + glm::vec3(0_f32, self.chase_height, 0_f32);
+
+        let catch_up = 1_f32 - (-self.chase_stiffness * delta_time).exp();
+        let position = glm::lerp(&self.chase_position.unwrap_or(wanted), &wanted, catch_up);
+        self.chase_position = Some(position);
+
+        Pose {
+            position,
+            orientation: rotation::quat_look_rotation(
+                &(target_position - position),
+                &glm::vec3(0_f32, 1_f32, 0_f32),
+            ),
+        }
+    }
+
+    fn cockpit_pose(&self, scene: &SceneGraph, target: NodeId) -> Pose {
+        let world_matrix = scene.world_matrix(target);
+        let offset = self.cockpit_offset;
+        Pose {
+            position: (world_matrix * glm::vec4(offset.x, offset.y, offset.z, 1_f32)).xyz(),
+            orientation: glm::quat_normalize(&glm::to_quat(&world_matrix)),
+        }
+    }
+}
+
+// Turns mouse movement into camera turns
+#[derive(Clone, Copy, Debug)]
+pub struct MouseLook {
+    // Radians per pixel
+    pub sensitivity: f32,
+    pub invert_y: bool,
+    // How long the turning takes to catch up with the mouse, in seconds. Zero turns right away.
+    pub smoothing: f32,
+    rate: glm::Vec2,
+}
+
+impl Default for MouseLook {
+    fn default() -> Self {
+        MouseLook {
+            sensitivity: 0.003,
+            invert_y: false,
+            smoothing: 0.05,
+            rate: glm::zero(),
+        }
+    }
+}
+
+impl MouseLook {
+    pub fn new(sensitivity: f32, invert_y: bool, smoothing: f32) -> MouseLook {
+        MouseLook {
+            sensitivity,
+            invert_y,
+            smoothing,
+            ..MouseLook::default()
+        }
+    }
+
+    // Give the mouse movement since the last frame in pixels, and get back how far to turn the
+    // camera, as the yaw and pitch for `Camera::turn`
+    pub fn look(&mut self, delta: (f32, f32), delta_time: f32) -> (f32, f32) {
+        let y = if self.invert_y { delta.1 } else { -delta.1 };
+        let turn = glm::vec2(-delta.0, y) * self.sensitivity;
+        if self.smoothing <= 0_f32 || delta_time <= 0_f32 {
+            self.rate = glm::zero();
+            return (turn.x, turn.y);
+        }
+
+        // Smooth the turning speed rather than the turn, so it does not depend on the frame rate
+        let catch_up = 1_f32 - (-delta_time / self.smoothing).exp();
+        self.rate += (turn / delta_time - self.rate) * catch_up;
+        (self.rate.x * delta_time, self.rate.y * delta_time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene_graph::SceneNode;
+
+    fn same(a: &Pose, b: &Pose) -> bool {
+        let orientation = glm::quat_dot(&a.orientation, &b.orientation).abs();
+        glm::distance(&a.position, &b.position) < 1e-5 && orientation > 1.0 - 1e-6
+    }
+
+    // A camera flying at the origin, and a node turned a quarter to the left away from it
+    fn setup() -> (Camera, SceneGraph, NodeId) {
+        let mut scene = SceneGraph::new();
+        let target = scene.add(SceneNode::new());
+        scene[target].set_position(glm::vec3(2.0, 1.0, -3.0));
+        scene[target].set_rotation(glm::vec3(0.0, glm::half_pi(), 0.0));
+        let mut camera = Camera::new(glm::zero(), 1.0);
+        camera.update(&scene, 0.0);
+        (camera, scene, target)
+    }
+
+    #[test]
+    fn transitions_start_at_the_old_mode_and_end_at_the_new_one() {
+        for mode in [CameraMode::Orbit, CameraMode::Chase, CameraMode::Cockpit] {
+            let (mut camera, scene, target) = setup();
+            let fly = camera.pose();
+            camera.set_mode(mode(target));
+            assert_eq!(camera.mode(), mode(target));
+            camera.update(&scene, 0.0);
+            assert!(same(&camera.pose(), &fly), "{:?}", camera.mode());
+
+            // Where the new mode alone puts the camera by then, with chasing catching up the
+            // same way
+            let mut target_mode = camera.clone();
+            target_mode.transition = None;
+            target_mode.update(&scene, TRANSITION_TIME);
+            camera.update(&scene, TRANSITION_TIME);
+            assert!(
+                same(&camera.pose(), &target_mode.pose()),
+                "{:?}",
+                camera.mode()
+            );
+            assert!(camera.transition.is_none());
+        }
+    }
+
+    #[test]
+    fn transitions_ease_in_and_out() {
+        let (mut camera, scene, target) = setup();
+        let from = camera.pose();
+        camera.set_mode(CameraMode::Orbit(target));
+        let to = camera.orbit_pose(&scene, target);
+
+        // Smoothstep is a half way there half way through, and slow at either end
+        let progress = |camera: &Camera| {
+            glm::distance(&camera.pose().position, &from.position)
+                / glm::distance(&to.position, &from.position)
+        };
+        camera.update(&scene, TRANSITION_TIME / 2.0);
+        assert!((progress(&camera) - 0.5).abs() < 1e-4);
+        let mut early = camera.clone();
+        early.transition.as_mut().unwrap().elapsed = 0.0;
+        early.update(&scene, TRANSITION_TIME / 10.0);
+        assert!((progress(&early) - 0.028).abs() < 1e-3);
+    }
+
+    #[test]
+    fn modes_place_the_camera() {
+        let (mut camera, scene, target) = setup();
+        let target_position = glm::vec3(2.0, 1.0, -3.0);
+
+        camera.set_mode(CameraMode::Orbit(target));
+        camera.update(&scene, 10.0);
+        let distance = glm::distance(&camera.eye(), &target_position);
+        assert!((distance - camera.orbit_distance).abs() < 1e-5);
+        let forward = glm::quat_rotate_vec3(&camera.pose().orientation, &glm::vec3(0.0, 0.0, -1.0));
+        let towards = glm::normalize(&(target_position - camera.eye()));
+        assert!(glm::distance(&forward, &towards) < 1e-5);
+
+        // The node faces -X, so the cockpit looks that way from inside it
+        camera.set_mode(CameraMode::Cockpit(target));
+        camera.update(&scene, 10.0);
+        let forward = glm::quat_rotate_vec3(&camera.pose().orientation, &glm::vec3(0.0, 0.0, -1.0));
+        assert!(glm::distance(&forward, &glm::vec3(-1.0, 0.0, 0.0)) < 1e-5);
+        assert!(glm::distance(&camera.eye(), &target_position) < 0.1);
+    }
+
+    #[test]
+    fn flying_carries_on_from_where_the_camera_is() {
+        let (mut camera, scene, target) = setup();
+        camera.set_mode(CameraMode::Orbit(target));
+        camera.update(&scene, 10.0);
+        let orbiting = camera.pose();
+
+        camera.set_mode(CameraMode::Fly);
+        camera.update(&scene, 0.0);
+        assert!(same(&camera.pose(), &orbiting));
+        camera.update(&scene, 10.0);
+        assert!(same(&camera.pose(), &orbiting));
+    }
+}
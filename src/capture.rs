@@ -0,0 +1,167 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use image::RgbImage;
+
+use crate::headless;
+
+// Saving what is on screen: single screenshots, and numbered image sequences for making videos.
+
+// Where screenshots go, right next to the report
+pub const SCREENSHOT_DIRECTORY: &str = "report/images";
+
+// Read the window's back buffer, with the frame that was just drawn in it
+pub unsafe fn read_window(width: u32, height: u32) -> RgbImage {
+    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+    gl::ReadBuffer(gl::BACK);
+    headless::read_pixels(width, height)
+}
+
+// Shrink an image by a whole factor, averaging each square of pixels into one
+pub fn downsample(image: &RgbImage, factor: u32) -> RgbImage {
+    let samples = factor * factor;
+    RgbImage::from_fn(image.width() / factor, image.height() / factor, |x, y| {
+        let mut sum = [0_u32; 3];
+        for dy in 0..factor {
+            for dx in 0..factor {
+                let pixel = image.get_pixel(x * factor + dx, y * factor + dy);
+                for (total, &channel) in sum.iter_mut().zip(pixel.0.iter()) {
+                    *total += channel as u32;
+                }
+            }
+        }
+        image::Rgb([
+            ((sum[0] + samples / 2) / samples) as u8,
+            ((sum[1] + samples / 2) / samples) as u8,
+            ((sum[2] + samples / 2) / samples) as u8,
+        ])
+    })
+}
+
+// Save a screenshot named after the time it was taken, like
+// `screenshot_2024-03-01_14-05-09.png`, returning where it went
+pub fn save_screenshot(image: &RgbImage, directory: &str) -> Result<String, String> {
+    std::fs::create_dir_all(directory)
+        .map_err(|e| format!("Failed to create {}: {}", directory, e))?;
+    let name = format!("screenshot_{}", timestamp());
+    // Never overwrite a screenshot taken in the same second
+    let mut path = format!("{}/{}.png", directory, name);
+    let mut copy = 1;
+    while std::path::Path::new(&path).exists() {
+        copy += 1;
+        path = format!("{}/{}_{}.png", directory, name, copy);
+    }
+    image
+        .save(&path)
+        .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    Ok(path)
+}
+
+// The time now in UTC, as year-month-day_hours-minutes-seconds
+fn timestamp() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    format_timestamp(seconds)
+}
+
+// Seconds since 1970-01-01 in UTC as year-month-day_hours-minutes-seconds
+fn format_timestamp(seconds: u64) -> String {
+    let (days, time) = (seconds / 86400, seconds % 86400);
+    let (year, month, day) = civil_date(days as i64);
+    format!(
+        "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+// The year, month and day of a day counted from 1970-01-01, from Howard Hinnant's date
+// algorithms
+fn civil_date(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+// Numbered frames written to a directory, frame_0000.png and on
+pub struct Sequence {
+    directory: String,
+    pub written: usize,
+}
+
+impl Sequence {
+    pub fn new(directory: &str) -> Result<Sequence, String> {
+        std::fs::create_dir_all(directory)
+            .map_err(|e| format!("Failed to create the output directory {}: {}", directory, e))?;
+        Ok(Sequence {
+            directory: directory.to_string(),
+            written: 0,
+        })
+    }
+
+    pub fn save(&mut self, image: &RgbImage) -> Result<(), String> {
+        let path = format!("{}/frame_{:04}.png", self.directory, self.written);
+        image
+            .save(&path)
+            .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        self.written += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(-1), (1969, 12, 31));
+        // Leap days, in a year divisible by 400 and one divisible by 4
+        assert_eq!(civil_date(11016), (2000, 2, 29));
+        assert_eq!(civil_date(19782), (2024, 2, 29));
+        // 2100 is divisible by 100 but not 400, so it has no leap day
+        assert_eq!(civil_date(47540), (2100, 2, 28));
+        assert_eq!(civil_date(47541), (2100, 3, 1));
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(format_timestamp(0), "1970-01-01_00-00-00");
+        assert_eq!(format_timestamp(946684799), "1999-12-31_23-59-59");
+        assert_eq!(format_timestamp(1709301909), "2024-03-01_14-05-09");
+    }
+
+    #[test]
+    fn downsample_averages_and_rounds() {
+        // Two 2x2 squares, with the red, green and blue of each of their pixels
+        let squares = [
+            [[0, 255, 1], [0, 255, 2], [1, 255, 3], [1, 255, 4]],
+            [[0, 255, 0], [0, 255, 1], [0, 255, 1], [1, 255, 1]],
+        ];
+        let image = RgbImage::from_fn(4, 2, |x, y| {
+            image::Rgb(squares[(x / 2) as usize][(y % 2 * 2 + x % 2) as usize])
+        });
+        let small = downsample(&image, 2);
+        assert_eq!(small.dimensions(), (2, 1));
+        // Halves round up, anything less rounds down, and full white stays white
+        assert_eq!(small.get_pixel(0, 0).0, [1, 255, 3]);
+        assert_eq!(small.get_pixel(1, 0).0, [0, 255, 1]);
+    }
+}
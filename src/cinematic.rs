@@ -0,0 +1,223 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::camera::{Camera, CameraMode};
+use crate::path::{CurveKind, Path};
+
+// Camera bookmarks and camera paths, for demos and figures.
+//
+// Both work on the free flying camera, storing where it is along with its yaw and pitch. A
+// camera path is recorded by sampling the camera a few times a second while flying around, and
+// played back along Catmull-Rom splines through the samples. Playback moves along the spline
+// by distance, easing in at the start and out at the end, so it is smooth however unevenly the
+// camera was flown while recording.
+
+// How often a recorded camera path is sampled, in seconds
+const SAMPLE_INTERVAL: f32 = 0.25;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraKey {
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl CameraKey {
+    // The key for where a camera is now. Only free flying cameras can be stored.
+    pub fn from_camera(camera: &Camera) -> Option<CameraKey> {
+        (camera.mode() == CameraMode::Fly).then_some(CameraKey {
+            position: camera.position.into(),
+            yaw: camera.yaw,
+            pitch: camera.pitch,
+        })
+    }
+
+    pub fn position(&self) -> glm::Vec3 {
+        glm::make_vec3(&self.position)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub name: String,
+    pub key: CameraKey,
+}
+
+// Bookmarks by their number, as written in `resources/bookmarks.ron`
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Bookmarks {
+    pub bookmarks: BTreeMap<u32, Bookmark>,
+}
+
+impl Bookmarks {
+    pub fn load(path: &str) -> Result<Bookmarks, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read bookmarks {}: {}", path, e))?;
+        ron::from_str(&text).map_err(|e| format!("Failed to parse bookmarks {}: {}", path, e))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|e| format!("Failed to serialize bookmarks: {}", e))?;
+        std::fs::write(path, text).map_err(|e| format!("Failed to write bookmarks {}: {}", path, e))
+    }
+}
+
+impl Bookmarks {
+    // Handle the console command for naming bookmarks, or return None for any other command:
+    //     bookmark <number> <name>  store the camera as a bookmark with a name
+    pub fn run_command(
+        &mut self,
+        line: &str,
+        camera: &Camera,
+        path: &str,
+    ) -> Option<Result<String, String>> {
+        let mut words = line.trim().splitn(3, char::is_whitespace);
+        if words.next() != Some("bookmark") {
+            return None;
+        }
+        let number = words.next().and_then(|number| number.parse::<u32>().ok());
+        let name = words.next().map(str::trim);
+        Some(match (number, name) {
+            (Some(number), Some(name)) => self.store(number, name, camera, path),
+            _ => Err("Usage: bookmark <number> <name>".to_string()),
+        })
+    }
+
+    // Store the camera as a bookmark and save them all
+    pub fn store(
+        &mut self,
+        number: u32,
+        name: &str,
+        camera: &Camera,
+        path: &str,
+    ) -> Result<String, String> {
+        let key = CameraKey::from_camera(camera)
+            .ok_or_else(|| "Only the free flying camera can be bookmarked".to_string())?;
+        let name = name.to_string();
+        self.bookmarks.insert(number, Bookmark { name, key });
+        self.save(path)
+            .map(|()| format!("Saved bookmark {} to {}", number, path))
+    }
+}
+
+// A recorded camera path, as written to a file
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CameraPath {
+    // How long the path took to record, and so how long playing it back takes
+    pub duration: f32,
+    pub keys: Vec<CameraKey>,
+}
+
+impl CameraPath {
+    pub fn load(path: &str) -> Result<CameraPath, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read camera path {}: {}", path, e))?;
+        ron::from_str(&text).map_err(|e| format!("Failed to parse camera path {}: {}", path, e))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|e| format!("Failed to serialize camera path: {}", e))?;
+        std::fs::write(path, text)
+            .map_err(|e| format!("Failed to write camera path {}: {}", path, e))
+    }
+}
+
+// Samples the camera while recording a path
+#[derive(Clone, Debug, Default)]
+pub struct PathRecorder {
+    path: CameraPath,
+    since_sample: f32,
+}
+
+impl PathRecorder {
+    pub fn record(&mut self, camera: &Camera, delta_time: f32) {
+        self.path.duration += delta_time;
+        self.since_sample += delta_time;
+        if self.path.keys.is_empty() || self.since_sample >= SAMPLE_INTERVAL {
+            if let Some(key) = CameraKey::from_camera(camera) {
+                self.path.keys.push(key);
+                self.since_sample = 0_f32;
+            }
+        }
+    }
+
+    // Stop recording, taking one last sample where the camera ended up
+    pub fn finish(mut self, camera: &Camera) -> CameraPath {
+        if let Some(key) = CameraKey::from_camera(camera) {
+            if self.path.keys.last() != Some(&key) {
+                self.path.keys.push(key);
+            }
+        }
+        self.path
+    }
+}
+
+// Plays a camera path back on the free flying camera
+pub struct Cinematic {
+    positions: Path,
+    // Pitch and yaw, kept in x and y
+    angles: Path,
+    duration: f32,
+    elapsed: f32,
+}
+
+impl Cinematic {
+    pub fn new(path: &CameraPath) -> Result<Cinematic, String> {
+        if path.keys.len() < 2 {
+            return Err("A camera path needs at least two keys to play".to_string());
+        }
+
+        // Turn the yaws into one continuous angle, so the camera never turns the long way round
+        let mut angles: Vec<glm::Vec3> = Vec::with_capacity(path.keys.len());
+        for key in &path.keys {
+            let yaw = match angles.last() {
+                Some(previous) => {
+                    let turns = ((key.yaw - previous.y) / glm::two_pi::<f32>()).round();
+                    key.yaw - turns * glm::two_pi::<f32>()
+                }
+                None => key.yaw,
+            };
+            angles.push(glm::vec3(key.pitch, yaw, 0_f32));
+        }
+
+        let positions = path.keys.iter().map(CameraKey::position).collect();
+        Ok(Cinematic {
+            positions: Path::new(CurveKind::CatmullRom, positions, false)?,
+            angles: Path::new(CurveKind::CatmullRom, angles, false)?,
+            duration: f32::max(path.duration, 1e-3),
+            elapsed: 0_f32,
+        })
+    }
+
+    // Where the camera starts
+    pub fn start(&self, camera: &mut Camera) {
+        let angles = self.angles.point(0_f32);
+        camera.fly_to(self.positions.point(0_f32), angles.y, angles.x);
+    }
+
+    // Move the camera along, returning false once the end has been reached
+    pub fn advance(&mut self, camera: &mut Camera, delta_time: f32) -> bool {
+        self.elapsed = f32::min(self.elapsed + delta_time, self.duration);
+        let t = self.elapsed / self.duration;
+        let eased = t * t * (3_f32 - 2_f32 * t);
+
+        // A path that only turns the camera has no length to move along
+        let length = self.positions.length();
+        let u = if length > 1e-6 {
+            self.positions.parameter_at_distance(eased * length)
+        } else {
+            eased * self.positions.segments() as f32
+        };
+        let angles = self.angles.point(u);
+        camera.position = self.positions.point(u);
+        camera.yaw = angles.y;
+        camera.pitch = angles.x;
+        self.elapsed < self.duration
+    }
+}
@@ -0,0 +1,193 @@
+extern crate nalgebra_glm as glm;
+
+use crate::mesh::Mesh;
+use crate::scene_graph::{NodeId, SceneGraph};
+
+// Keeping things above the terrain.
+//
+// The terrain is treated as a height field: for any x and z there is one ground height, the
+// highest triangle above that point. Its triangles are sorted into a grid of cells on the XZ
+// plane, so finding the ground only has to look at the triangles in one cell. Moving nodes are
+// tested with the corners of the bounding boxes of everything in their subtree.
+
+// Cells along each side of the grid
+const GRID_SIZE: usize = 64;
+
+// A surface flatter than this (the cosine of its slope) can be landed on
+const LANDING_SLOPE: f32 = 0.94;
+// Anything slower than this, in scene units per second, comes to rest when touching down
+const LANDING_SPEED: f32 = 0.3;
+
+pub struct TerrainCollider {
+    triangles: Vec<[glm::Vec3; 3]>,
+    min: glm::Vec2,
+    cell_size: glm::Vec2,
+    // Indices into `triangles` for every cell, row by row
+    cells: Vec<Vec<usize>>,
+}
+
+// Where a moving node has sunk into the ground. `depth` is how far it has to be pushed up.
+#[derive(Clone, Copy, Debug)]
+pub struct Contact {
+    pub depth: f32,
+    pub normal: glm::Vec3,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GroundState {
+    Airborne,
+    Sliding,
+    Landed,
+}
+
+impl TerrainCollider {
+    // Build from the terrain mesh, placed in the world by `world_matrix`
+    pub fn new(mesh: &Mesh, world_matrix: &glm::Mat4) -> TerrainCollider {
+        let vertex = |i: u32| {
+            let v = &mesh.vertices[3 * i as usize..3 * i as usize + 3];
+            let p = world_matrix * glm::vec4(v[0] / 100.0, v[1] / 100.0, v[2] / 100.0, 1.0);
+            p.xyz()
+        };
+        let triangles: Vec<[glm::Vec3; 3]> = mesh
+            .indices
+            .chunks(3)
+            .map(|t| [vertex(t[0]), vertex(t[1]), vertex(t[2])])
+            .collect();
+
+        let xz = |p: &glm::Vec3| glm::vec2(p.x, p.z);
+        let (min, max) = triangles.iter().flatten().fold(
+            (glm::vec2(f32::MAX, f32::MAX), glm::vec2(f32::MIN, f32::MIN)),
+            |(min, max), p| (glm::min2(&min, &xz(p)), glm::max2(&max, &xz(p))),
+        );
+        let cell_size = glm::max(&((max - min) / GRID_SIZE as f32), 1e-6);
+
+        let mut collider = TerrainCollider {
+            triangles: vec![],
+            min,
+            cell_size,
+            cells: vec![vec![]; GRID_SIZE * GRID_SIZE],
+        };
+        for (i, triangle) in triangles.iter().enumerate() {
+            let low = triangle.iter().map(xz).fold(max, |a, b| glm::min2(&a, &b));
+            let high = triangle.iter().map(xz).fold(min, |a, b| glm::max2(&a, &b));
+            let (x0, z0) = collider.cell(&low);
+            let (x1, z1) = collider.cell(&high);
+            for z in z0..=z1 {
+                for x in x0..=x1 {
+                    collider.cells[z * GRID_SIZE + x].push(i);
+                }
+            }
+        }
+        collider.triangles = triangles;
+        collider
+    }
+
+    fn cell(&self, point: &glm::Vec2) -> (usize, usize) {
+        let cell = (point - self.min).component_div(&self.cell_size);
+        let clamp = |c: f32| (c.max(0.0) as usize).min(GRID_SIZE - 1);
+        (clamp(cell.x), clamp(cell.y))
+    }
+
+    // The height of the ground at x and z along with its upwards facing normal, or None
+    // outside the terrain
+    pub fn ground(&self, x: f32, z: f32) -> Option<(f32, glm::Vec3)> {
+        let point = glm::vec2(x, z);
+        let (cell_x, cell_z) = self.cell(&point);
+        let mut ground: Option<(f32, glm::Vec3)> = None;
+        for &i in &self.cells[cell_z * GRID_SIZE + cell_x] {
+            let [a, b, c] = self.triangles[i];
+            // Barycentric coordinates of the point in the triangle seen from above
+            let (ab, ac, ap) = (
+                glm::vec2(b.x - a.x, b.z - a.z),
+                glm::vec2(c.x - a.x, c.z - a.z),
+                glm::vec2(x - a.x, z - a.z),
+            );
+            let area = ab.x * ac.y - ab.y * ac.x;
+            if area.abs() < 1e-12 {
+                continue;
+            }
+            let v = (ap.x * ac.y - ap.y * ac.x) / area;
+            let w = (ab.x * ap.y - ab.y * ap.x) / area;
+            if v < 0.0 || w < 0.0 || v + w > 1.0 {
+                continue;
+            }
+
+            let height = a.y + v * (b.y - a.y) + w * (c.y - a.y);
+            if ground.is_none_or(|(highest, _)| height > highest) {
+                let normal = glm::normalize(&glm::cross(&(b - a), &(c - a)));
+                let normal = if normal.y < 0.0 { -normal } else { normal };
+                ground = Some((height, normal));
+            }
+        }
+        ground
+    }
+
+    // How far below the ground the lowest of some points is
+    pub fn contact(&self, points: &[glm::Vec3]) -> Option<Contact> {
+        points
+            .iter()
+            .filter_map(|p| {
+                self.ground(p.x, p.z).map(|(height, normal)| Contact {
+                    depth: height - p.y,
+                    normal,
+                })
+            })
+            .filter(|contact| contact.depth > 0.0)
+            .max_by(|a, b| a.depth.total_cmp(&b.depth))
+    }
+
+    // Test a node, using the bounding boxes of it and everything below it
+    pub fn node_contact(&self, scene: &SceneGraph, node: NodeId) -> Option<Contact> {
+        self.contact(&world_corners(scene, node))
+    }
+
+    // Push a node back up if it has sunk into the ground. The node's parent must not be
+    // rotated, which holds for everything placed directly on the terrain.
+    pub fn clamp_node(&self, scene: &mut SceneGraph, node: NodeId) -> Option<Contact> {
+        let contact = self.node_contact(scene, node)?;
+        let position = scene[node].position();
+        scene[node].set_position(position + glm::vec3(0_f32, contact.depth, 0_f32));
+        Some(contact)
+    }
+}
+
+// The corners of every bounding box in a subtree, in world space
+pub fn world_corners(scene: &SceneGraph, node: NodeId) -> Vec<glm::Vec3> {
+    scene
+        .query(node, "**")
+        .into_iter()
+        .filter_map(|n| scene[n].bounds.map(|bounds| (n, bounds)))
+        .flat_map(|(n, bounds)| {
+            let world_matrix = scene.world_matrix(n);
+            bounds
+                .corners()
+                .map(|corner| (world_matrix * glm::vec4(corner.x, corner.y, corner.z, 1.0)).xyz())
+        })
+        .collect()
+}
+
+// Respond to a contact for something moving with `velocity`: push it out of the ground and
+// let it slide along the surface, or come to rest if it touched down gently on flat ground
+pub fn resolve(
+    contact: Option<Contact>,
+    position: &mut glm::Vec3,
+    velocity: &mut glm::Vec3,
+) -> GroundState {
+    let contact = match contact {
+        Some(contact) => contact,
+        None => return GroundState::Airborne,
+    };
+
+    position.y += contact.depth;
+    let into_ground = glm::dot(velocity, &contact.normal);
+    if into_ground < 0.0 {
+        *velocity -= into_ground * contact.normal;
+    }
+
+    if contact.normal.y > LANDING_SLOPE && glm::length(velocity) < LANDING_SPEED {
+        *velocity = glm::zero();
+        GroundState::Landed
+    } else {
+        GroundState::Sliding
+    }
+}
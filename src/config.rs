@@ -0,0 +1,275 @@
+use serde::{Deserialize, Serialize};
+
+use crate::camera::MouseLook;
+
+// How the viewer is set up: which scene it shows, the window, and where frames go when there is
+// no window.
+//
+// Settings are read from a RON file (see `resources/config.ron`), where anything left out keeps
+// its default, and then from the command line, which overrides the file. Recording, replaying
+// and the golden images are only chosen on the command line, since they are about a single run.
+
+pub const CONFIG_PATH: &str = "resources/config.ron";
+
+pub const USAGE: &str = "\
+Usage: gloom-rs [options]
+
+Options:
+    --config <file>          Read settings from this file instead of resources/config.ron
+    --scene <file>           The scene file to load
+    --width <pixels>         Width of the window, or of the frames when headless
+    --height <pixels>        Height of the window, or of the frames when headless
+    --vsync, --no-vsync      Wait for the display before showing each frame, or not
+    --msaa <samples>         Samples per pixel in the window, 0 to turn multisampling off
+    --fullscreen             Fill the screen instead of opening a window
+    --windowed               Open a window even if the config file asks for fullscreen
+    --headless <directory>   Render without a window, writing frames to the directory
+    --frames <count>         How many frames to write when headless
+    --sequence <directory>   Write every frame shown in the window to the directory
+    --helicopters <count>    Fly at most this many helicopters besides the player's
+    --rotor-speed <factor>   How fast the rotors spin, 1 being normal
+    --clear-color <r,g,b>    The color behind the scene from 0 to 1, with an optional alpha
+    --mouse-sensitivity <radians>
+                             How far the camera turns per pixel of mouse movement
+    --invert-y, --no-invert-y
+                             Look down when the mouse moves up, or not
+    --mouse-smoothing <seconds>
+                             How long turning takes to catch up with the mouse, 0 for none
+    --record <file>          Record all input to a file
+    --replay <file>          Play a recording back instead of taking input
+    --golden                 Check the golden images, see golden.rs
+    --bless                  With --golden, make the current renders the new references
+    --help                   Print this and quit";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub scene: String,
+    pub width: u32,
+    pub height: u32,
+    pub vsync: bool,
+    // Samples per pixel, 0 for none. Only the window is multisampled.
+    pub msaa: u16,
+    pub fullscreen: bool,
+    // Render without a window, writing this many frames to the directory
+    pub headless: Option<String>,
+    pub frames: usize,
+    // Write every frame shown in the window to the directory
+    pub sequence: Option<String>,
+    // The helicopters in the scene file past this many are left out
+    pub helicopters: Option<usize>,
+    pub rotor_speed: f32,
+    pub clear_color: [f32; 4],
+    // Mouse look, see `camera::MouseLook`
+    pub mouse_sensitivity: f32,
+    pub invert_y: bool,
+    pub mouse_smoothing: f32,
+
+    #[serde(skip)]
+    pub record: Option<String>,
+    #[serde(skip)]
+    pub replay: Option<String>,
+    #[serde(skip)]
+    pub golden: bool,
+    #[serde(skip)]
+    pub bless: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let mouse_look = MouseLook::default();
+        Config {
+            scene: crate::SCENE_PATH.to_string(),
+            width: crate::INITIAL_SCREEN_W,
+            height: crate::INITIAL_SCREEN_H,
+            vsync: true,
+            msaa: 0,
+            fullscreen: false,
+            headless: None,
+            frames: crate::DEFAULT_HEADLESS_FRAMES,
+            sequence: None,
+            helicopters: None,
+            rotor_speed: 1.0,
+            clear_color: crate::CLEAR_COLOR,
+            mouse_sensitivity: mouse_look.sensitivity,
+            invert_y: mouse_look.invert_y,
+            mouse_smoothing: mouse_look.smoothing,
+            record: None,
+            replay: None,
+            golden: false,
+            bless: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Config, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {}: {}", path, e))?;
+        ron::from_str(&text).map_err(|e| format!("Failed to parse config file {}: {}", path, e))
+    }
+
+    // Read the config file named by --config, or the default one if it is there, and override
+    // it with the rest of the arguments. `args` does not include the program name.
+    pub fn from_args(args: &[String]) -> Result<Config, String> {
+        let config_path = args
+            .iter()
+            .position(|arg| arg == "--config")
+            .map(|i| {
+                args.get(i + 1)
+                    .ok_or_else(|| "--config needs a file".to_string())
+            })
+            .transpose()?;
+        let mut config = match config_path {
+            Some(path) => Config::load(path)?,
+            None if std::path::Path::new(CONFIG_PATH).exists() => Config::load(CONFIG_PATH)?,
+            None => Config::default(),
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| format!("{} needs a value", arg))
+            };
+            match arg.as_str() {
+                "--config" => {
+                    value()?;
+                }
+                "--scene" => config.scene = value()?,
+                "--width" => config.width = parse(arg, &value()?)?,
+                "--height" => config.height = parse(arg, &value()?)?,
+                "--vsync" => config.vsync = true,
+                "--no-vsync" => config.vsync = false,
+                "--msaa" => config.msaa = parse(arg, &value()?)?,
+                "--fullscreen" => config.fullscreen = true,
+                "--windowed" => config.fullscreen = false,
+                "--headless" => config.headless = Some(value()?),
+                "--frames" => config.frames = parse(arg, &value()?)?,
+                "--sequence" => config.sequence = Some(value()?),
+                "--helicopters" => config.helicopters = Some(parse(arg, &value()?)?),
+                "--rotor-speed" => config.rotor_speed = parse(arg, &value()?)?,
+                "--clear-color" => config.clear_color = parse_color(&value()?)?,
+                "--mouse-sensitivity" => config.mouse_sensitivity = parse(arg, &value()?)?,
+                "--invert-y" => config.invert_y = true,
+                "--no-invert-y" => config.invert_y = false,
+                "--mouse-smoothing" => config.mouse_smoothing = parse(arg, &value()?)?,
+                "--record" => config.record = Some(value()?),
+                "--replay" => config.replay = Some(value()?),
+                "--golden" => config.golden = true,
+                "--bless" => config.bless = true,
+                _ => return Err(format!("Unknown argument {}\n\n{}", arg, USAGE)),
+            }
+        }
+
+        if config.width == 0 || config.height == 0 {
+            return Err(format!(
+                "The window can not be {}x{}",
+                config.width, config.height
+            ));
+        }
+        Ok(config)
+    }
+
+    pub fn mouse_look(&self) -> MouseLook {
+        MouseLook::new(self.mouse_sensitivity, self.invert_y, self.mouse_smoothing)
+    }
+}
+
+fn parse<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} needs a number, not {}", name, value))
+}
+
+// Three or four comma separated numbers, with alpha 1 if it is left out
+fn parse_color(value: &str) -> Result<[f32; 4], String> {
+    let channels = value
+        .split(',')
+        .map(|channel| parse("--clear-color", channel.trim()))
+        .collect::<Result<Vec<f32>, String>>()?;
+    match channels[..] {
+        [r, g, b] => Ok([r, g, b, 1.0]),
+        [r, g, b, a] => Ok([r, g, b, a]),
+        _ => Err(format!(
+            "--clear-color needs three or four numbers, not {}",
+            value
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_args(args: &[&str]) -> Result<Config, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Config::from_args(&args)
+    }
+
+    #[test]
+    fn config_without_a_file() {
+        assert_eq!(
+            from_args(&["--config"]).unwrap_err(),
+            "--config needs a file"
+        );
+        let error = from_args(&["--width", "640", "--config"]).unwrap_err();
+        assert_eq!(error, "--config needs a file");
+    }
+
+    #[test]
+    fn zero_size() {
+        let error = from_args(&["--width", "0"]).unwrap_err();
+        assert!(error.starts_with("The window can not be 0x"), "{}", error);
+        let error = from_args(&["--width", "640", "--height", "0"]).unwrap_err();
+        assert_eq!(error, "The window can not be 640x0");
+    }
+
+    #[test]
+    fn unknown_argument() {
+        let error = from_args(&["--colour", "1,1,1"]).unwrap_err();
+        assert!(error.starts_with("Unknown argument --colour"), "{}", error);
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(parse_color("0.1,0.2,0.3"), Ok([0.1, 0.2, 0.3, 1.0]));
+        assert_eq!(parse_color("0, 0.5, 1, 0.25"), Ok([0.0, 0.5, 1.0, 0.25]));
+        let config = from_args(&["--clear-color", "1,0,0"]).unwrap();
+        assert_eq!(config.clear_color, [1.0, 0.0, 0.0, 1.0]);
+
+        assert_eq!(
+            parse_color("1,0"),
+            Err("--clear-color needs three or four numbers, not 1,0".to_string())
+        );
+        assert_eq!(
+            parse_color("1,0,0,1,1"),
+            Err("--clear-color needs three or four numbers, not 1,0,0,1,1".to_string())
+        );
+        assert_eq!(
+            parse_color("1,red,0"),
+            Err("--clear-color needs a number, not red".to_string())
+        );
+        assert!(from_args(&["--clear-color", ""]).is_err());
+        assert!(from_args(&["--clear-color"]).is_err());
+    }
+
+    #[test]
+    fn mouse_look_settings() {
+        let config = from_args(&[
+            "--mouse-sensitivity",
+            "0.01",
+            "--invert-y",
+            "--mouse-smoothing",
+            "0",
+        ])
+        .unwrap();
+        let mouse_look = config.mouse_look();
+        assert_eq!(mouse_look.sensitivity, 0.01);
+        assert!(mouse_look.invert_y);
+        assert_eq!(mouse_look.smoothing, 0.0);
+        let error = from_args(&["--mouse-sensitivity", "fast"]).unwrap_err();
+        assert_eq!(error, "--mouse-sensitivity needs a number, not fast");
+    }
+}
@@ -0,0 +1,118 @@
+use glutin::window::Window;
+use glutin::{ContextWrapper, PossiblyCurrent};
+
+use crate::capture::{self, Sequence};
+use crate::headless::{Framebuffer, HeadlessContext};
+
+// Where finished frames go: onto the window, or into numbered PNG files when running headless.
+// Frames on the window can be written out as well, as an image sequence for making videos.
+
+pub enum Display {
+    Window {
+        context: ContextWrapper<PossiblyCurrent, Window>,
+        sequence: Option<Sequence>,
+    },
+    Headless {
+        framebuffer: Framebuffer,
+        sequence: Sequence,
+        // How many frames to write
+        frames: usize,
+        // Kept alive for as long as anything is drawn, and dropped after the framebuffer
+        _context: HeadlessContext,
+    },
+}
+
+impl Display {
+    // Start rendering without a window, making the context current on this thread
+    pub fn headless(
+        width: u32,
+        height: u32,
+        output: &str,
+        frames: usize,
+    ) -> Result<Display, String> {
+        let sequence = Sequence::new(output)?;
+        let context = HeadlessContext::new()?;
+        let framebuffer = unsafe { Framebuffer::new(width, height)? };
+        Ok(Display::Headless {
+            framebuffer,
+            sequence,
+            frames,
+            _context: context,
+        })
+    }
+
+    pub fn window(&self) -> Option<&Window> {
+        match self {
+            Display::Window { context, .. } => Some(context.window()),
+            Display::Headless { .. } => None,
+        }
+    }
+
+    // Whether every frame is written out, so the clock should step by the same amount every
+    // frame instead of following the time that actually passed
+    pub fn writes_frames(&self) -> bool {
+        match self {
+            Display::Window { sequence, .. } => sequence.is_some(),
+            Display::Headless { .. } => true,
+        }
+    }
+
+    // Whether every frame there is to write has been written, checked before drawing each one
+    pub fn finished(&self) -> bool {
+        match self {
+            Display::Window { .. } => false,
+            Display::Headless {
+                sequence, frames, ..
+            } => sequence.written >= *frames,
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        match self {
+            Display::Window { context, .. } => {
+                context.resize(glutin::dpi::PhysicalSize::new(width, height))
+            }
+            Display::Headless { framebuffer, .. } => unsafe { framebuffer.resize(width, height) },
+        }
+    }
+
+    // Draw into the window, or the offscreen framebuffer
+    pub unsafe fn bind(&self) {
+        match self {
+            Display::Window { .. } => gl::BindFramebuffer(gl::FRAMEBUFFER, 0),
+            Display::Headless { framebuffer, .. } => framebuffer.bind(),
+        }
+    }
+
+    // The frame that was just drawn, as an image
+    pub unsafe fn read_image(&self) -> image::RgbImage {
+        match self {
+            Display::Window { context, .. } => {
+                let size = context.window().inner_size();
+                capture::read_window(size.width, size.height)
+            }
+            Display::Headless { framebuffer, .. } => framebuffer.read_image(),
+        }
+    }
+
+    // Show the frame that was just drawn, writing it to a file first if writing frames
+    pub fn present(&mut self) -> Result<(), String> {
+        if self.writes_frames() {
+            let image = unsafe { self.read_image() };
+            match self {
+                Display::Window {
+                    sequence: Some(sequence),
+                    ..
+                }
+                | Display::Headless { sequence, .. } => sequence.save(&image)?,
+                Display::Window { sequence: None, .. } => {}
+            }
+        }
+        match self {
+            Display::Window { context, .. } => context
+                .swap_buffers()
+                .map_err(|e| format!("Failed to swap buffers: {}", e)),
+            Display::Headless { .. } => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,203 @@
+extern crate nalgebra_glm as glm;
+
+use crate::scene_graph::{NodeId, SceneGraph};
+use crate::toolbox::{self, Heading};
+
+// Steering a group of aircraft.
+//
+// Every member of a flock steers by adding up a few simple urges: keep away from those too
+// close (separation), fly the same way as the neighbours (alignment), stay with the group
+// (cohesion), and either hold a slot in a formation around the leader or just follow behind
+// it. Each member keeps its own position and velocity, so each gets its own heading.
+//
+// Positions are in the same units as `Heading`, a hundred to a scene unit, like the circuit.
+
+#[derive(Clone, Copy, Debug)]
+pub struct FlockParameters {
+    pub separation_radius: f32,
+    pub neighbour_radius: f32,
+    pub separation: f32,
+    pub alignment: f32,
+    pub cohesion: f32,
+    // How hard members go for their formation slot, or for the spot behind the leader
+    pub leader_following: f32,
+    // How far behind the leader members without a slot try to stay
+    pub follow_distance: f32,
+    // Whether the formation turns with the leader. Slots far from a leader in a tight turn
+    // would have to fly much faster than it, so otherwise they keep their world directions.
+    pub turn_with_leader: bool,
+    pub max_speed: f32,
+    pub max_acceleration: f32,
+}
+
+impl Default for FlockParameters {
+    fn default() -> Self {
+        FlockParameters {
+            separation_radius: 8.0,
+            neighbour_radius: 25.0,
+            separation: 400.0,
+            alignment: 0.5,
+            cohesion: 0.2,
+            leader_following: 4.0,
+            follow_distance: 10.0,
+            turn_with_leader: true,
+            max_speed: 60.0,
+            max_acceleration: 80.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Boid {
+    pub node: NodeId,
+    pub position: glm::Vec3,
+    pub velocity: glm::Vec3,
+    pub acceleration: glm::Vec3,
+    // Where to fly relative to the leader: x to its right, y above and z behind it, or plain
+    // world axes if the formation does not turn with the leader
+    pub slot: Option<glm::Vec3>,
+}
+
+impl Boid {
+    pub fn new(node: NodeId, position: glm::Vec3, slot: Option<glm::Vec3>) -> Boid {
+        Boid {
+            node,
+            position,
+            velocity: glm::zero(),
+            acceleration: glm::zero(),
+            slot,
+        }
+    }
+
+    pub fn heading(&self) -> Heading {
+        toolbox::heading_from_motion(&self.position, &self.velocity, &self.acceleration)
+    }
+}
+
+// Where the flock is headed. The leader may be one of the members, or just a point moving along
+// a path.
+#[derive(Clone, Copy, Debug)]
+pub struct Leader {
+    pub position: glm::Vec3,
+    pub velocity: glm::Vec3,
+}
+
+impl Leader {
+    // Turn an offset in the leader's frame into world space. Formations that do not turn
+    // use world space offsets to begin with.
+    fn to_world(self, offset: &glm::Vec3, turn: bool) -> glm::Vec3 {
+        if !turn {
+            return *offset;
+        }
+        let up = glm::vec3(0_f32, 1_f32, 0_f32);
+        let forward = if glm::length(&self.velocity) > 1e-6 {
+            glm::normalize(&self.velocity)
+        } else {
+            glm::vec3(0_f32, 0_f32, -1_f32)
+        };
+        let right = glm::normalize(&glm::cross(&forward, &up));
+        let up = glm::cross(&right, &forward);
+        right * offset.x + up * offset.y - forward * offset.z
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Flock {
+    pub parameters: FlockParameters,
+    pub boids: Vec<Boid>,
+}
+
+impl Flock {
+    pub fn new(boids: Vec<Boid>) -> Flock {
+        Flock {
+            parameters: FlockParameters::default(),
+            boids,
+        }
+    }
+
+    // Put every member in its place around the leader, flying along with it
+    pub fn form_up(&mut self, leader: &Leader) {
+        let (follow_distance, turn) = (
+            self.parameters.follow_distance,
+            self.parameters.turn_with_leader,
+        );
+        for boid in self.boids.iter_mut() {
+            let offset = boid
+                .slot
+                .unwrap_or(glm::vec3(0_f32, 0_f32, follow_distance));
+            boid.position = leader.position + leader.to_world(&offset, turn);
+            boid.velocity = leader.velocity;
+            boid.acceleration = glm::zero();
+        }
+    }
+
+    pub fn step(&mut self, leader: &Leader, dt: f32) {
+        let accelerations: Vec<glm::Vec3> = (0..self.boids.len())
+            .map(|i| self.steering(i, leader))
+            .collect();
+
+        let p = &self.parameters;
+        for (boid, acceleration) in self.boids.iter_mut().zip(accelerations) {
+            boid.acceleration = limit(&acceleration, p.max_acceleration);
+            boid.velocity = limit(&(boid.velocity + boid.acceleration * dt), p.max_speed);
+            boid.position += boid.velocity * dt;
+        }
+    }
+
+    fn steering(&self, i: usize, leader: &Leader) -> glm::Vec3 {
+        let p = &self.parameters;
+        let boid = &self.boids[i];
+
+        let mut separation: glm::Vec3 = glm::zero();
+        let mut neighbour_velocity: glm::Vec3 = glm::zero();
+        let mut neighbour_position: glm::Vec3 = glm::zero();
+        let mut neighbours = 0;
+        for (j, other) in self.boids.iter().enumerate() {
+            let away = boid.position - other.position;
+            let distance = glm::length(&away);
+            if j == i || distance > p.neighbour_radius {
+                continue;
+            }
+            if distance < p.separation_radius && distance > 1e-6 {
+                separation += away / (distance * distance);
+            }
+            neighbour_velocity += other.velocity;
+            neighbour_position += other.position;
+            neighbours += 1;
+        }
+
+        let mut steering = separation * p.separation;
+        if neighbours > 0 {
+            let n = neighbours as f32;
+            steering += (neighbour_velocity / n - boid.velocity) * p.alignment;
+            steering += (neighbour_position / n - boid.position) * p.cohesion;
+        }
+
+        // Head for the slot, matching the leader's velocity once there
+        let offset = boid
+            .slot
+            .unwrap_or(glm::vec3(0_f32, 0_f32, p.follow_distance));
+        let target = leader.position + leader.to_world(&offset, p.turn_with_leader);
+        let desired_velocity = leader.velocity + (target - boid.position) * p.leader_following;
+        steering + (desired_velocity - boid.velocity) * p.leader_following
+    }
+
+    // Place every member's node at its position, facing the way it flies. `scale` converts
+    // from flock units to the units of the nodes' parent.
+    pub fn apply(&self, scene: &mut SceneGraph, scale: f32) {
+        for boid in &self.boids {
+            let heading = boid.heading();
+            scene[boid.node].set_position(glm::vec3(heading.x, heading.y, heading.z) * scale);
+            scene[boid.node].set_rotation(glm::vec3(heading.pitch, heading.yaw, heading.roll));
+        }
+    }
+}
+
+fn limit(v: &glm::Vec3, max_length: f32) -> glm::Vec3 {
+    let length = glm::length(v);
+    if length > max_length {
+        v * (max_length / length)
+    } else {
+        *v
+    }
+}
@@ -0,0 +1,357 @@
+extern crate nalgebra_glm as glm;
+
+use std::path::Path;
+
+use image::{Rgb, RgbImage};
+
+use crate::camera::{Camera, CameraMode};
+use crate::headless::{Framebuffer, HeadlessContext};
+use crate::mesh::Mesh;
+use crate::scene_file::{self, NodeDescription, SceneDescription};
+use crate::scene_graph::{NodeId, SceneGraph, SceneNode};
+use crate::{shader, toolbox};
+
+// Golden image tests, for catching changes to how the scene is drawn.
+//
+// A few fixed scenes are rendered headless and compared with the reference images in
+// `resources/golden`. Nothing in them depends on a clock: the cameras are placed by hand, and
+// the helicopter is frozen at a fixed time in its animations. A scene passes when hardly any
+// pixel is further from the reference than the tolerance, which leaves room for GPUs that
+// rasterize edges a little differently. For every scene that fails, the render and an image
+// showing where it differs are written to `target/golden`. Scenes whose models or reference are
+// missing are skipped, since the models are not part of the repository.
+
+const REFERENCE_DIRECTORY: &str = "resources/golden";
+const OUTPUT_DIRECTORY: &str = "target/golden";
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+
+// How far any colour channel of a pixel may be from the reference, and the fraction of pixels
+// allowed to be further off than that
+const TOLERANCE: u8 = 8;
+const MAX_DIFFERENT_PIXELS: f64 = 0.002;
+
+// When the helicopter is frozen in its animations, in seconds
+const ANIMATION_TIME: f32 = 0.37;
+
+struct GoldenScene {
+    name: &'static str,
+    // Build the scene, returning its root and the camera to look at it through
+    build: fn(&mut SceneGraph) -> Result<(NodeId, Camera), Problem>,
+}
+
+// Why a scene did not pass
+enum Problem {
+    // Something it needs is not there, so it can not be checked
+    Missing(String),
+    Failed(String),
+}
+
+impl From<String> for Problem {
+    fn from(message: String) -> Problem {
+        Problem::Failed(message)
+    }
+}
+
+const SCENES: [GoldenScene; 3] = [
+    GoldenScene {
+        name: "primitives",
+        build: primitives,
+    },
+    GoldenScene {
+        name: "terrain",
+        build: terrain,
+    },
+    GoldenScene {
+        name: "helicopter",
+        build: helicopter,
+    },
+];
+
+// Render every scene and compare it with its reference, or write the renders as the new
+// references when blessing them. Returns an error if any scene failed.
+pub fn run(bless: bool) -> Result<(), String> {
+    let _context = HeadlessContext::new()?;
+    run_in_context(bless)
+}
+
+// The same, with a headless context already current on this thread
+fn run_in_context(bless: bool) -> Result<(), String> {
+    let framebuffer = unsafe {
+        crate::set_up_opengl();
+        Framebuffer::new(WIDTH, HEIGHT)?
+    };
+    let simple_shader = unsafe {
+        shader::ShaderBuilder::new()
+            .attach_file("./shaders/simple.frag")
+            .attach_file("./shaders/simple.vert")
+            .link()
+    };
+    unsafe { simple_shader.activate() };
+
+    let directory = if bless {
+        REFERENCE_DIRECTORY
+    } else {
+        OUTPUT_DIRECTORY
+    };
+    std::fs::create_dir_all(directory)
+        .map_err(|e| format!("Failed to create {}: {}", directory, e))?;
+
+    let (mut failed, mut skipped) = (0, 0);
+    for golden in SCENES.iter() {
+        let result = unsafe { render(&framebuffer, golden) }.and_then(|image| {
+            let reference_path = format!("{}/{}.png", REFERENCE_DIRECTORY, golden.name);
+            if bless {
+                save(&image, &reference_path)?;
+                Ok(format!("wrote {}", reference_path))
+            } else {
+                check(golden.name, &image, &reference_path)
+            }
+        });
+        match result {
+            Ok(message) => println!("{}: {}", golden.name, message),
+            Err(Problem::Missing(message)) => {
+                println!("{}: skipped, {}", golden.name, message);
+                skipped += 1;
+            }
+            Err(Problem::Failed(message)) => {
+                println!("{}: FAILED, {}", golden.name, message);
+                failed += 1;
+            }
+        }
+    }
+    println!(
+        "{} passed, {} skipped, {} failed",
+        SCENES.len() - failed - skipped,
+        skipped,
+        failed
+    );
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!(
+            "{} of {} golden images failed",
+            failed,
+            SCENES.len()
+        )),
+    }
+}
+
+unsafe fn render(framebuffer: &Framebuffer, golden: &GoldenScene) -> Result<RgbImage, Problem> {
+    let mut scene = SceneGraph::new();
+    let (root, camera) = (golden.build)(&mut scene)?;
+
+    framebuffer.bind();
+    gl::Viewport(0, 0, WIDTH as i32, HEIGHT as i32);
+    camera.projection.set_depth_state();
+    let [r, g, b, a] = crate::CLEAR_COLOR;
+    gl::ClearColor(r, g, b, a);
+    gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+    crate::draw_scene(&scene, root, &camera.view_projection(), &camera.eye());
+    let image = framebuffer.read_image();
+    // Every scene uploads its own meshes, so free them before the next one
+    crate::remove_from_scene(&mut scene, root);
+    Ok(image)
+}
+
+fn save(image: &RgbImage, path: &str) -> Result<(), String> {
+    image
+        .save(path)
+        .map_err(|e| format!("Failed to write {}: {}", path, e))
+}
+
+// Compare a render with its reference, writing it and where it differs to the output directory
+// if it is too far off
+fn check(name: &str, image: &RgbImage, reference_path: &str) -> Result<String, Problem> {
+    let actual_path = format!("{}/{}.png", OUTPUT_DIRECTORY, name);
+    if !Path::new(reference_path).exists() {
+        save(image, &actual_path)?;
+        return Err(Problem::Missing(format!(
+            "there is no reference {}, the render is in {}. Run with --bless to make it the \
+             reference.",
+            reference_path, actual_path
+        )));
+    }
+    let reference = image::open(reference_path)
+        .map_err(|e| format!("could not read {}: {}", reference_path, e))?
+        .to_rgb8();
+    if reference.dimensions() != image.dimensions() {
+        save(image, &actual_path)?;
+        return Err(Problem::Failed(format!(
+            "the reference is {:?} but the render is {:?}, the render is in {}",
+            reference.dimensions(),
+            image.dimensions(),
+            actual_path
+        )));
+    }
+
+    let (different, diff) = difference(image, &reference);
+    let allowed = (MAX_DIFFERENT_PIXELS * (WIDTH * HEIGHT) as f64) as usize;
+    if different > allowed {
+        let diff_path = format!("{}/{}.diff.png", OUTPUT_DIRECTORY, name);
+        save(image, &actual_path)?;
+        save(&diff, &diff_path)?;
+        return Err(Problem::Failed(format!(
+            "{} pixels differ where {} are allowed, see {} and {}",
+            different, allowed, actual_path, diff_path
+        )));
+    }
+    Ok(format!("ok, {} pixels differ", different))
+}
+
+// Count the pixels with a colour channel further from the reference than the tolerance, and
+// show them in red on top of a faded copy of the reference
+fn difference(image: &RgbImage, reference: &RgbImage) -> (usize, RgbImage) {
+    let mut different = 0;
+    let diff = RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let actual = image.get_pixel(x, y);
+        let expected = reference.get_pixel(x, y);
+        let off = actual
+            .0
+            .iter()
+            .zip(expected.0.iter())
+            .any(|(a, e)| a.abs_diff(*e) > TOLERANCE);
+        if off {
+            different += 1;
+            Rgb([255, 0, 0])
+        } else {
+            let gray = expected.0.iter().map(|&c| c as u32).sum::<u32>() / 3 / 4;
+            Rgb([gray as u8; 3])
+        }
+    });
+    (different, diff)
+}
+
+fn camera_at(position: glm::Vec3, pitch: f32) -> Camera {
+    let mut camera = Camera::new(position, WIDTH as f32 / HEIGHT as f32);
+    camera.pitch = pitch;
+    camera
+}
+
+fn add_mesh(scene: &mut SceneGraph, name: &str, mesh: &Mesh) -> NodeId {
+    let vao = unsafe { crate::create_vao(mesh) };
+    scene.add(SceneNode::from_vao(vao, mesh.indices.len() as i32).with_name(name))
+}
+
+fn instantiate(scene: &mut SceneGraph, description: &SceneDescription) -> Result<NodeId, String> {
+    description.instantiate(scene, &mut |_, mesh| unsafe { crate::create_vao(mesh) })
+}
+
+// Make sure every model `node` and everything below it draws is there, including those of the
+// prefabs they are copies of, without needing any the rest of the scene file draws
+fn check_models(description: &SceneDescription, node: &NodeDescription) -> Result<(), Problem> {
+    let mut nodes = vec![node];
+    let mut prefabs: Vec<&str> = vec![];
+    while let Some(node) = nodes.pop() {
+        match &node.prefab {
+            Some(name) if !prefabs.contains(&name.as_str()) => {
+                prefabs.push(name);
+                nodes.extend(description.prefabs.get(name));
+            }
+            Some(_) => {}
+            None => {
+                if let Some(source) = &node.mesh {
+                    if !Path::new(&source.path).exists() {
+                        return Err(Problem::Missing(format!(
+                            "there is no model {}",
+                            source.path
+                        )));
+                    }
+                }
+            }
+        }
+        nodes.extend(node.children.iter());
+    }
+    Ok(())
+}
+
+// A cube and a sphere standing on a floor, made without any files
+fn primitives(scene: &mut SceneGraph) -> Result<(NodeId, Camera), Problem> {
+    let root = scene.add(SceneNode::new().with_name("primitives"));
+    let floor = add_mesh(scene, "floor", &Mesh::plane(400.0, [0.5, 0.5, 0.5, 1.0]));
+    let cube = add_mesh(scene, "cube", &Mesh::cube(60.0, [0.8, 0.2, 0.2, 1.0]));
+    let sphere = add_mesh(
+        scene,
+        "sphere",
+        &Mesh::sphere(40.0, 32, 16, [0.2, 0.4, 0.8, 1.0]),
+    );
+    scene[floor].set_position(glm::vec3(0.0, -0.4, 0.0));
+    scene[cube].set_position(glm::vec3(-0.6, -0.1, 0.0));
+    scene[cube].set_rotation(glm::vec3(0.0, 0.6, 0.0));
+    scene[sphere].set_position(glm::vec3(0.6, 0.0, 0.0));
+    for &node in [floor, cube, sphere].iter() {
+        scene.add_child(root, node);
+    }
+
+    // From the side the light is coming from, so most faces are lit
+    let mut camera = camera_at(glm::vec3(-0.6, 0.5, -1.8), -0.3);
+    camera.yaw = 3.5;
+    camera.update(scene, 0.0);
+    Ok((root, camera))
+}
+
+// The terrain from the scene file on its own
+fn terrain(scene: &mut SceneGraph) -> Result<(NodeId, Camera), Problem> {
+    let mut description = scene_file::load(crate::SCENE_PATH)?;
+    description.root.children.clear();
+    check_models(&description, &description.root)?;
+    let root = instantiate(scene, &description)?;
+
+    let mut camera = camera_at(glm::vec3(0.0, 1.5, 4.0), -0.35);
+    camera.update(scene, 0.0);
+    Ok((root, camera))
+}
+
+// The player's helicopter from the scene file on its own, hovering with its rotors frozen part
+// of the way through a turn
+fn helicopter(scene: &mut SceneGraph) -> Result<(NodeId, Camera), Problem> {
+    let description = scene_file::load(crate::SCENE_PATH)?;
+    let clips = description.load_clips()?;
+    let mut player = description
+        .root
+        .find("player")
+        .ok_or_else(|| "The scene has no node named player".to_string())?
+        .clone();
+    check_models(&description, &player)?;
+    player.position = [0.0; 3];
+    player.rotation = [0.0; 3];
+    let root = instantiate(
+        scene,
+        &SceneDescription {
+            root: player,
+            ..description
+        },
+    )?;
+
+    let mut animator = toolbox::helicopter_animator(scene, root, &clips, "hover")?;
+    animator.advance(ANIMATION_TIME);
+    animator.apply(scene);
+
+    // Orbit it from the side the light is coming from, long enough for the camera to finish
+    // easing into orbit
+    let mut camera = camera_at(glm::zero(), 0.0);
+    camera.orbit_yaw = -2.2;
+    camera.set_mode(CameraMode::Orbit(root));
+    camera.update(scene, 1.0);
+    Ok((root, camera))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Skipped where there is no EGL to render with, like on machines without Mesa
+    #[test]
+    fn golden_images_match() {
+        let _context = match HeadlessContext::new() {
+            Ok(context) => context,
+            Err(e) => {
+                println!("Skipping the golden images: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = run_in_context(false) {
+            panic!("{}", e);
+        }
+    }
+}
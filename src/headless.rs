@@ -0,0 +1,236 @@
+use std::ptr;
+
+use khronos_egl as egl;
+
+// Rendering without a window, for machines without a display.
+//
+// An OpenGL context is made through EGL on Mesa's surfaceless platform, so it needs neither a
+// window nor a display server, and works with the llvmpipe software renderer. libEGL is only
+// loaded when a headless context is made, so everything else runs without it. Without a window
+// there is no default framebuffer either, so everything is drawn into a `Framebuffer` and read
+// back into an image.
+
+// From EGL_MESA_platform_surfaceless
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+
+pub struct HeadlessContext {
+    // Boxed, since it holds every EGL function pointer
+    egl: Box<egl::DynamicInstance<egl::EGL1_4>>,
+    display: egl::Display,
+    context: egl::Context,
+    // A tiny pbuffer to make the context current with, for EGL versions that need a surface
+    surface: Option<egl::Surface>,
+}
+
+impl HeadlessContext {
+    // Create an OpenGL 4.5 core context, make it current on this thread and load the OpenGL
+    // functions
+    pub fn new() -> Result<HeadlessContext, String> {
+        let egl = unsafe { egl::DynamicInstance::<egl::EGL1_4>::load_required() }
+            .map_err(|e| format!("Failed to load libEGL for headless rendering: {}", e))?;
+        let error = |what: &str| {
+            let what = what.to_string();
+            move |e: egl::Error| format!("Failed to {} for headless rendering: {}", what, e)
+        };
+        let has_extension = |display: Option<egl::Display>, name: &str| {
+            egl.query_string(display, egl::EXTENSIONS)
+                .is_ok_and(|extensions| extensions.to_string_lossy().split(' ').any(|e| e == name))
+        };
+
+        // Fall back to the default display where the surfaceless platform or EGL 1.5 is missing
+        let surfaceless = match egl.upcast::<egl::EGL1_5>() {
+            Some(egl) if has_extension(None, "EGL_MESA_platform_surfaceless") => unsafe {
+                let attributes = [egl::ATTRIB_NONE];
+                egl.get_platform_display(
+                    PLATFORM_SURFACELESS_MESA,
+                    egl::DEFAULT_DISPLAY,
+                    &attributes,
+                )
+                .ok()
+            },
+            _ => None,
+        };
+        let display = surfaceless
+            .or_else(|| unsafe { egl.get_display(egl::DEFAULT_DISPLAY) })
+            .ok_or_else(|| "There is no EGL display for headless rendering".to_string())?;
+        egl.initialize(display).map_err(error("initialize EGL"))?;
+
+        let config_attributes = [
+            egl::SURFACE_TYPE,
+            egl::PBUFFER_BIT,
+            egl::RENDERABLE_TYPE,
+            egl::OPENGL_BIT,
+            egl::NONE,
+        ];
+        let config = egl
+            .choose_first_config(display, &config_attributes)
+            .map_err(error("choose a config"))?
+            .ok_or_else(|| "There is no EGL config for desktop OpenGL".to_string())?;
+
+        egl.bind_api(egl::OPENGL_API)
+            .map_err(error("bind OpenGL"))?;
+        let context_attributes = [
+            egl::CONTEXT_MAJOR_VERSION,
+            4,
+            egl::CONTEXT_MINOR_VERSION,
+            5,
+            egl::CONTEXT_OPENGL_PROFILE_MASK,
+            egl::CONTEXT_OPENGL_CORE_PROFILE_BIT,
+            egl::NONE,
+        ];
+        let context = egl
+            .create_context(display, config, None, &context_attributes)
+            .map_err(error("create an OpenGL 4.5 context"))?;
+
+        let surface = if has_extension(Some(display), "EGL_KHR_surfaceless_context") {
+            None
+        } else {
+            let attributes = [egl::WIDTH, 1, egl::HEIGHT, 1, egl::NONE];
+            Some(
+                egl.create_pbuffer_surface(display, config, &attributes)
+                    .map_err(error("create a pbuffer"))?,
+            )
+        };
+        egl.make_current(display, surface, surface, Some(context))
+            .map_err(error("make the context current"))?;
+
+        gl::load_with(|symbol| {
+            egl.get_proc_address(symbol)
+                .map_or(ptr::null(), |function| function as *const _)
+        });
+        Ok(HeadlessContext {
+            egl: Box::new(egl),
+            display,
+            context,
+            surface,
+        })
+    }
+}
+
+impl Drop for HeadlessContext {
+    fn drop(&mut self) {
+        let egl = &self.egl;
+        egl.make_current(self.display, None, None, None).ok();
+        egl.destroy_context(self.display, self.context).ok();
+        if let Some(surface) = self.surface {
+            egl.destroy_surface(self.display, surface).ok();
+        }
+        egl.terminate(self.display).ok();
+    }
+}
+
+// An offscreen color and depth buffer to draw frames into
+pub struct Framebuffer {
+    framebuffer: u32,
+    color_buffer: u32,
+    depth_buffer: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Framebuffer {
+    pub unsafe fn new(width: u32, height: u32) -> Result<Framebuffer, String> {
+        let mut framebuffer = 0;
+        gl::GenFramebuffers(1, &mut framebuffer);
+        let mut color_buffer = 0;
+        gl::GenRenderbuffers(1, &mut color_buffer);
+        let mut depth_buffer = 0;
+        gl::GenRenderbuffers(1, &mut depth_buffer);
+
+        let mut target = Framebuffer {
+            framebuffer,
+            color_buffer,
+            depth_buffer,
+            width,
+            height,
+        };
+        target.allocate()?;
+        Ok(target)
+    }
+
+    pub unsafe fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        if let Err(e) = self.allocate() {
+            panic!("{}", e);
+        }
+    }
+
+    unsafe fn allocate(&mut self) -> Result<(), String> {
+        let (width, height) = (self.width as i32, self.height as i32);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, self.color_buffer);
+        gl::RenderbufferStorage(gl::RENDERBUFFER, gl::RGBA8, width, height);
+        // A float depth buffer, so reversed depth gets all of its precision
+        gl::BindRenderbuffer(gl::RENDERBUFFER, self.depth_buffer);
+        gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH_COMPONENT32F, width, height);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+        gl::FramebufferRenderbuffer(
+            gl::FRAMEBUFFER,
+            gl::COLOR_ATTACHMENT0,
+            gl::RENDERBUFFER,
+            self.color_buffer,
+        );
+        gl::FramebufferRenderbuffer(
+            gl::FRAMEBUFFER,
+            gl::DEPTH_ATTACHMENT,
+            gl::RENDERBUFFER,
+            self.depth_buffer,
+        );
+        let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            return Err(format!(
+                "The {}x{} offscreen framebuffer is incomplete",
+                self.width, self.height
+            ));
+        }
+        Ok(())
+    }
+
+    // The widest or tallest framebuffer the driver allows
+    pub unsafe fn max_size() -> u32 {
+        let mut size = 0;
+        gl::GetIntegerv(gl::MAX_RENDERBUFFER_SIZE, &mut size);
+        size.max(0) as u32
+    }
+
+    // Draw into this framebuffer, and read from it
+    pub unsafe fn bind(&self) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+    }
+
+    pub unsafe fn read_image(&self) -> image::RgbImage {
+        self.bind();
+        read_pixels(self.width, self.height)
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.framebuffer);
+            gl::DeleteRenderbuffers(1, &self.color_buffer);
+            gl::DeleteRenderbuffers(1, &self.depth_buffer);
+        }
+    }
+}
+
+// Read the bottom left of the framebuffer bound for reading into an image, turning it the right
+// way up since OpenGL starts at the bottom row
+pub unsafe fn read_pixels(width: u32, height: u32) -> image::RgbImage {
+    let mut pixels = vec![0_u8; 3 * width as usize * height as usize];
+    gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+    gl::ReadPixels(
+        0,
+        0,
+        width as i32,
+        height as i32,
+        gl::RGB,
+        gl::UNSIGNED_BYTE,
+        pixels.as_mut_ptr() as *mut std::ffi::c_void,
+    );
+    let image = image::RgbImage::from_raw(width, height, pixels).unwrap();
+    image::imageops::flip_vertical(&image)
+}
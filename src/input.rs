@@ -0,0 +1,348 @@
+use std::collections::BTreeMap;
+
+use glutin::event::{MouseButton, VirtualKeyCode};
+use serde::{Deserialize, Serialize};
+
+// Named actions, and the keys, mouse buttons and axes that trigger them.
+//
+// Everything that reacts to input asks for an action by name, like "move_forward", instead of
+// looking for a particular key. Which inputs trigger which action is kept in a RON file (see
+// `resources/bindings.ron`) mapping every action to a list of bindings, and can be changed
+// while running with the commands in `Actions::run_command`.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Axis {
+    // Mouse movement since the last frame, in pixels
+    MouseX,
+    MouseY,
+    // Steps of the scroll wheel since the last frame, positive away from the user
+    Scroll,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+    Axis(Axis),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Bindings {
+    actions: BTreeMap<String, Vec<Binding>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use Binding::*;
+        use VirtualKeyCode as K;
+
+        let actions = [
+            // Flying the camera
+            ("move_forward", vec![Key(K::W)]),
+            ("move_back", vec![Key(K::S)]),
+            ("move_left", vec![Key(K::A)]),
+            ("move_right", vec![Key(K::D)]),
+            ("move_up", vec![Key(K::Space)]),
+            ("move_down", vec![Key(K::LShift)]),
+            ("yaw_left", vec![Key(K::Left)]),
+            ("yaw_right", vec![Key(K::Right)]),
+            ("pitch_up", vec![Key(K::Up)]),
+            ("pitch_down", vec![Key(K::Down)]),
+            ("look_x", vec![Axis(self::Axis::MouseX)]),
+            ("look_y", vec![Axis(self::Axis::MouseY)]),
+            ("zoom", vec![Axis(self::Axis::Scroll)]),
+            ("grab_cursor", vec![Key(K::G)]),
+            ("switch_camera", vec![Key(K::V)]),
+            ("switch_projection", vec![Key(K::P)]),
+            ("switch_layout", vec![Key(K::Tab)]),
+            // Camera bookmarks and paths
+            ("bookmark_1", vec![Key(K::Key1)]),
+            ("bookmark_2", vec![Key(K::Key2)]),
+            ("bookmark_3", vec![Key(K::Key3)]),
+            ("bookmark_4", vec![Key(K::Key4)]),
+            ("bookmark_5", vec![Key(K::Key5)]),
+            ("bookmark_6", vec![Key(K::Key6)]),
+            ("bookmark_7", vec![Key(K::Key7)]),
+            ("bookmark_8", vec![Key(K::Key8)]),
+            ("bookmark_9", vec![Key(K::Key9)]),
+            ("set_bookmark", vec![Key(K::LControl)]),
+            ("record_camera_path", vec![Key(K::F5)]),
+            ("play_camera_path", vec![Key(K::F6)]),
+            // Flying the helicopter
+            ("collective_up", vec![Key(K::R)]),
+            ("collective_down", vec![Key(K::F)]),
+            ("cyclic_forward", vec![Key(K::I)]),
+            ("cyclic_back", vec![Key(K::K)]),
+            ("cyclic_left", vec![Key(K::J)]),
+            ("cyclic_right", vec![Key(K::L)]),
+            ("pedal_left", vec![Key(K::U)]),
+            ("pedal_right", vec![Key(K::O)]),
+            // Everything else
+            ("pick", vec![Mouse(MouseButton::Left)]),
+            ("save_scene", vec![Key(K::F2)]),
+            ("screenshot", vec![Key(K::F12)]),
+            ("high_res_screenshot", vec![Key(K::F9)]),
+            ("quit", vec![Key(K::Escape), Key(K::Q)]),
+        ];
+        Bindings {
+            actions: actions
+                .iter()
+                .map(|(action, bindings)| (action.to_string(), bindings.clone()))
+                .collect(),
+        }
+    }
+}
+
+impl Bindings {
+    // Read bindings from a file. Actions the file leaves out keep their default bindings.
+    pub fn load(path: &str) -> Result<Bindings, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read key bindings {}: {}", path, e))?;
+        let loaded: Bindings = ron::from_str(&text)
+            .map_err(|e| format!("Failed to parse key bindings {}: {}", path, e))?;
+
+        let mut bindings = Bindings::default();
+        bindings.actions.extend(loaded.actions);
+        Ok(bindings)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|e| format!("Failed to serialize key bindings: {}", e))?;
+        std::fs::write(path, text)
+            .map_err(|e| format!("Failed to write key bindings {}: {}", path, e))
+    }
+
+    pub fn get(&self, action: &str) -> &[Binding] {
+        self.actions.get(action).map_or(&[], |bindings| bindings)
+    }
+
+    // Trigger an action with these bindings only
+    pub fn bind(&mut self, action: &str, bindings: Vec<Binding>) {
+        self.actions.insert(action.to_string(), bindings);
+    }
+
+    pub fn actions(&self) -> impl Iterator<Item = (&str, &[Binding])> {
+        self.actions
+            .iter()
+            .map(|(action, bindings)| (action.as_str(), bindings.as_slice()))
+    }
+}
+
+// What the input looks like this frame, and what the actions make of it
+pub struct Actions {
+    pub bindings: Bindings,
+    keys: Vec<VirtualKeyCode>,
+    buttons: Vec<MouseButton>,
+    previous_keys: Vec<VirtualKeyCode>,
+    previous_buttons: Vec<MouseButton>,
+    // Buttons pressed since the last frame, which count as pressed even if they are up again
+    clicks: Vec<MouseButton>,
+    mouse_delta: (f32, f32),
+    scroll: f32,
+    // The action to bind to the next key or mouse button pressed
+    capturing: Option<String>,
+}
+
+impl Actions {
+    pub fn new(bindings: Bindings) -> Actions {
+        Actions {
+            bindings,
+            keys: vec![],
+            buttons: vec![],
+            previous_keys: vec![],
+            previous_buttons: vec![],
+            clicks: vec![],
+            mouse_delta: (0.0, 0.0),
+            scroll: 0.0,
+            capturing: None,
+        }
+    }
+
+    // Start a new frame with the keys and mouse buttons held down now, the buttons clicked
+    // since the last frame, and how far the mouse and scroll wheel moved since the last frame
+    pub fn update(
+        &mut self,
+        keys: &[VirtualKeyCode],
+        buttons: &[MouseButton],
+        clicks: &[MouseButton],
+        mouse_delta: (f32, f32),
+        scroll: f32,
+    ) {
+        // A click already let go is held for this frame, so it is not lost between frames
+        let mut buttons = buttons.to_vec();
+        for &button in clicks {
+            if !buttons.contains(&button) {
+                buttons.push(button);
+            }
+        }
+        self.previous_keys = std::mem::replace(&mut self.keys, keys.to_vec());
+        self.previous_buttons = std::mem::replace(&mut self.buttons, buttons);
+        self.clicks = clicks.to_vec();
+        self.mouse_delta = mouse_delta;
+        self.scroll = scroll;
+
+        if self.capturing.is_some() {
+            let key = self
+                .keys
+                .iter()
+                .find(|key| !self.previous_keys.contains(key))
+                .map(|&key| Binding::Key(key));
+            let button = self
+                .buttons
+                .iter()
+                .find(|&&button| !self.was_down(&Binding::Mouse(button)))
+                .map(|&button| Binding::Mouse(button));
+            if let Some(binding) = key.or(button) {
+                let action = self.capturing.take().unwrap();
+                println!("Bound {} to {:?}", action, binding);
+                self.bindings.bind(&action, vec![binding]);
+                // Don't let the key do anything else this frame
+                self.previous_keys.clone_from(&self.keys);
+                self.previous_buttons.clone_from(&self.buttons);
+                self.clicks.clear();
+            }
+        }
+    }
+
+    fn is_down(&self, binding: &Binding) -> bool {
+        match binding {
+            Binding::Key(key) => self.keys.contains(key),
+            Binding::Mouse(button) => self.buttons.contains(button),
+            Binding::Axis(_) => self.axis_value(binding) != 0.0,
+        }
+    }
+
+    fn was_down(&self, binding: &Binding) -> bool {
+        match binding {
+            Binding::Key(key) => self.previous_keys.contains(key),
+            // Let go and clicked again since the last frame is a new press
+            Binding::Mouse(button) => {
+                self.previous_buttons.contains(button) && !self.clicks.contains(button)
+            }
+            Binding::Axis(_) => false,
+        }
+    }
+
+    fn axis_value(&self, binding: &Binding) -> f32 {
+        match binding {
+            Binding::Axis(Axis::MouseX) => self.mouse_delta.0,
+            Binding::Axis(Axis::MouseY) => self.mouse_delta.1,
+            Binding::Axis(Axis::Scroll) => self.scroll,
+            _ => 0.0,
+        }
+    }
+
+    // Whether the action is triggered right now
+    pub fn held(&self, action: &str) -> bool {
+        let capturing = self.capturing.is_some();
+        !capturing && self.bindings.get(action).iter().any(|b| self.is_down(b))
+    }
+
+    // Whether the action was triggered this frame, but not the last one
+    pub fn pressed(&self, action: &str) -> bool {
+        let bindings = self.bindings.get(action);
+        self.held(action) && !bindings.iter().any(|b| self.was_down(b))
+    }
+
+    // How much the action is triggered: one for every key or button held down, plus however
+    // far its axes moved
+    pub fn value(&self, action: &str) -> f32 {
+        if self.capturing.is_some() {
+            return 0.0;
+        }
+        self.bindings
+            .get(action)
+            .iter()
+            .map(|binding| match binding {
+                Binding::Axis(_) => self.axis_value(binding),
+                _ => self.is_down(binding) as i32 as f32,
+            })
+            .sum()
+    }
+
+    // The difference between two opposite actions, like "move_right" and "move_left"
+    pub fn axis(&self, positive: &str, negative: &str) -> f32 {
+        self.value(positive) - self.value(negative)
+    }
+
+    // Handle a line typed into the console, returning what to tell the user:
+    //     bind <action>             bind the action to the next key or mouse button pressed
+    //     bind <action> <bindings>  bind the action to a list like [Key(W), Mouse(Left)]
+    //     unbind <action>           leave the action without bindings
+    //     bindings                  list every action and its bindings
+    //     save                      write the bindings to `path`
+    pub fn run_command(&mut self, line: &str, path: &str) -> Result<String, String> {
+        let mut words = line.trim().splitn(3, char::is_whitespace);
+        let command = words.next().unwrap_or_default();
+        let action = words.next().map(str::trim);
+        let argument = words.next().map(str::trim);
+
+        let known_action = |action: Option<&str>| match action {
+            Some(action) if self.bindings.actions.contains_key(action) => Ok(action.to_string()),
+            Some(action) => Err(format!("There is no action named {}", action)),
+            None => Err(format!("{} needs an action", command)),
+        };
+        match (command, argument) {
+            ("bind", None) => {
+                let action = known_action(action)?;
+                self.capturing = Some(action.clone());
+                Ok(format!("Press a key or mouse button for {}", action))
+            }
+            ("bind", Some(argument)) => {
+                let action = known_action(action)?;
+                // Allow a single binding without the brackets
+                let bindings = ron::from_str::<Vec<Binding>>(argument)
+                    .or_else(|_| ron::from_str::<Binding>(argument).map(|b| vec![b]))
+                    .map_err(|e| format!("Failed to parse {}: {}", argument, e))?;
+                self.bindings.bind(&action, bindings);
+                Ok(format!(
+                    "Bound {} to {:?}",
+                    action,
+                    self.bindings.get(&action)
+                ))
+            }
+            ("unbind", None) => {
+                let action = known_action(action)?;
+                self.bindings.bind(&action, vec![]);
+                Ok(format!("Unbound {}", action))
+            }
+            ("bindings", None) => Ok(self
+                .bindings
+                .actions()
+                .map(|(action, bindings)| format!("{}: {:?}", action, bindings))
+                .collect::<Vec<String>>()
+                .join("\n")),
+            ("save", None) => self
+                .bindings
+                .save(path)
+                .map(|()| format!("Saved the key bindings to {}", path)),
+            _ => Err(format!("Unknown command: {}", line.trim())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn click_between_frames_is_pressed_once() {
+        let mut actions = Actions::new(Bindings::default());
+        let left = [MouseButton::Left];
+        // Pressed and let go before the frame, so only the click is left to see
+        actions.update(&[], &[], &left, (0.0, 0.0), 0.0);
+        assert!(actions.pressed("pick"));
+        actions.update(&[], &[], &[], (0.0, 0.0), 0.0);
+        assert!(!actions.held("pick"));
+        // Held down over two frames is a single press
+        actions.update(&[], &left, &left, (0.0, 0.0), 0.0);
+        assert!(actions.pressed("pick"));
+        actions.update(&[], &left, &[], (0.0, 0.0), 0.0);
+        assert!(!actions.pressed("pick"));
+        // Let go and clicked again while it looked held is a new press
+        actions.update(&[], &left, &left, (0.0, 0.0), 0.0);
+        assert!(actions.pressed("pick"));
+    }
+}
@@ -8,32 +8,85 @@
 #![allow(unused_variables)]
 */
 extern crate nalgebra_glm as glm;
-use std::sync::{Arc, Mutex, RwLock};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
 use std::thread;
 use std::{mem, os::raw::c_void, ptr};
 
+mod animation;
+mod blending;
+mod camera;
+mod capture;
+mod cinematic;
+mod collision;
+mod config;
+mod display;
+mod flocking;
+mod golden;
+mod headless;
+mod input;
 mod mesh;
+mod path;
+mod picking;
+mod recording;
+mod rotation;
+mod scene_file;
 mod scene_graph;
 mod shader;
+mod simulation;
 mod toolbox;
 mod util;
-
+mod viewport;
+mod world;
+
+use camera::{Camera, CameraMode, Projection};
+use cinematic::{Bookmarks, CameraPath, Cinematic, PathRecorder};
+use collision::TerrainCollider;
+use config::Config;
+use display::Display;
 use glutin::event::{
     DeviceEvent,
     ElementState::{Pressed, Released},
-    Event, KeyboardInput,
-    VirtualKeyCode::{self, *},
-    WindowEvent,
+    Event, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
 };
 use glutin::event_loop::ControlFlow;
+use glutin::window::CursorGrabMode;
+use headless::Framebuffer;
+use input::{Actions, Bindings};
 use itertools::izip;
-use mesh::{Helicopter, Mesh, Terrain};
-use scene_graph::SceneNode;
-
-// initial window size
+use mesh::Mesh;
+use picking::PickingBuffer;
+use recording::{InputFrame, Recorder, Replay, Settings};
+use scene_graph::{NodeId, SceneGraph};
+use simulation::Clock;
+use viewport::{Layout, Rect, View};
+use world::World;
+
+// initial window size, unless the config says otherwise
 const INITIAL_SCREEN_W: u32 = 800;
 const INITIAL_SCREEN_H: u32 = 600;
 
+// the scene to load at startup unless the config says otherwise, and where to save it when F2 is pressed
+const SCENE_PATH: &str = "resources/scene.ron";
+const SAVED_SCENE_PATH: &str = "resources/saved_scene.ron";
+
+// which keys and mouse buttons do what, see `input.rs`
+const BINDINGS_PATH: &str = "resources/bindings.ron";
+
+// camera bookmarks, and the camera path that is recorded and played back
+const BOOKMARKS_PATH: &str = "resources/bookmarks.ron";
+const CAMERA_PATH_PATH: &str = "resources/camera_path.ron";
+
+// the night sky, unless the config says otherwise
+const CLEAR_COLOR: [f32; 4] = [0.035, 0.046, 0.078, 1.0];
+
+// how much larger than the window high resolution screenshots are, and how many samples along
+// each side go into every one of their pixels
+const SCREENSHOT_SCALE: u32 = 2;
+const SCREENSHOT_SUPERSAMPLING: u32 = 2;
+
+// how many frames to write when rendering headless, unless told otherwise
+const DEFAULT_HEADLESS_FRAMES: usize = 60;
+
 // == // Helper functions to make interacting with OpenGL a little bit prettier. You *WILL* need these! // == //
 
 // Get the size of an arbitrary array of numbers measured in bytes
@@ -145,111 +198,191 @@ unsafe fn create_vao(mesh: &Mesh) -> u32 {
     array
 }
 
+// Free a VAO made by `create_vao`, along with the vertex and index buffers it references
+unsafe fn delete_vao(vao: u32) {
+    gl::BindVertexArray(vao);
+    let mut vertex_buffer = 0;
+    gl::GetVertexAttribiv(
+        0,
+        gl::VERTEX_ATTRIB_ARRAY_BUFFER_BINDING,
+        &mut vertex_buffer,
+    );
+    let mut index_buffer = 0;
+    gl::GetIntegerv(gl::ELEMENT_ARRAY_BUFFER_BINDING, &mut index_buffer);
+    gl::BindVertexArray(0);
+
+    let buffers = [vertex_buffer as u32, index_buffer as u32];
+    gl::DeleteBuffers(2, buffers.as_ptr());
+    gl::DeleteVertexArrays(1, &vao);
+}
+
+// Remove a node and its subtree from the scene, freeing the VAOs nothing else draws anymore
+unsafe fn remove_from_scene(scene: &mut SceneGraph, node: NodeId) {
+    for vao in scene.remove(node) {
+        delete_vao(vao);
+    }
+}
+
 unsafe fn draw_scene(
-    node: &scene_graph::SceneNode,
+    scene: &SceneGraph,
+    node: NodeId,
     view_projection_matrix: &glm::Mat4,
-    transformation_so_far: &glm::Mat4,
     camera_pos: &glm::TVec3<f32>,
 ) {
+    walk_scene(
+        scene,
+        node,
+        view_projection_matrix,
+        &mut |_, model_matrix| {
+            gl::UniformMatrix4fv(1, 1, gl::FALSE, model_matrix.as_ptr());
+            gl::Uniform3f(2, camera_pos.x, camera_pos.y, camera_pos.z);
+        },
+    );
+}
+
+// The traversal shared by every pass over the scene graph. Sets the model-view-projection
+// uniform at location 0 from the node's cached world matrix, lets `set_uniforms` set anything
+// else the active shader needs (given the node's local matrix), and draws the node before
+// recursing into its children.
+unsafe fn walk_scene(
+    scene: &SceneGraph,
+    node_id: NodeId,
+    view_projection_matrix: &glm::Mat4,
+    set_uniforms: &mut dyn FnMut(NodeId, &glm::Mat4),
+) {
+    let node = &scene[node_id];
+
     // Perform any logic needed before drawing the node
     // Check if node is drawable, if so: set uniforms, bind VAO and draw VAO
     // Recurse
     // == // Issue the necessary gl:: commands to draw your scene here
 
-    let translate_origin: glm::Mat4 = glm::translation(&-node.reference_point);
-    let translate_reference: glm::Mat4 = glm::translation(&node.reference_point);
-
-    let pitch_transform = glm::rotation(node.rotation.x, &glm::vec3(1_f32, 0_f32, 0_f32));
-    let yaw_transform = glm::rotation(node.rotation.y, &glm::vec3(0_f32, 1_f32, 0_f32));
-    let roll_transform = glm::rotation(
-        node.rotation.z,
-        &glm::vec3(
-            node.rotation.y.sin(),
-            node.rotation.x.sin(),
-            node.rotation.y.cos() * node.rotation.x.cos(),
-        ),
-    );
+    if node.index_count > 0 {
+        let model_view_projection = view_projection_matrix * scene.world_matrix(node_id);
 
-    let translation = glm::translation(&node.position);
+        gl::UniformMatrix4fv(0, 1, gl::FALSE, model_view_projection.as_ptr());
+        set_uniforms(node_id, &node.local_matrix());
 
-    let model_matrix = translation
-        * translate_reference
-        * roll_transform
-        * yaw_transform
-        * pitch_transform
-        * translate_origin;
-
-    let model_view_projection = view_projection_matrix * transformation_so_far * model_matrix;
+        gl::BindVertexArray(node.vao_id);
+        gl::DrawElements(
+            gl::TRIANGLES,
+            node.index_count,
+            gl::UNSIGNED_INT,
+            ptr::null(),
+        );
+    }
 
-    gl::UniformMatrix4fv(0, 1, gl::FALSE, model_view_projection.as_ptr());
-    gl::UniformMatrix4fv(1, 1, gl::FALSE, model_matrix.as_ptr());
-    gl::Uniform3f(2, camera_pos.x, camera_pos.y, camera_pos.z);
+    for &child in node.children() {
+        walk_scene(scene, child, view_projection_matrix, set_uniforms);
+    }
+}
 
-    gl::BindVertexArray(node.vao_id);
-    gl::DrawElements(
-        gl::TRIANGLES,
-        node.index_count,
-        gl::UNSIGNED_INT,
-        ptr::null(),
-    );
+// Render every drawable node with a unique ID and return the node under the cursor, if any.
+// IDs are handed out in traversal order, starting at 1 since 0 is the cleared background.
+unsafe fn pick_scene(
+    picking_buffer: &PickingBuffer,
+    scene: &SceneGraph,
+    root: NodeId,
+    view_projection_matrix: &glm::Mat4,
+    cursor: (u32, u32),
+) -> Option<NodeId> {
+    let mut drawn_nodes: Vec<NodeId> = vec![];
+
+    picking_buffer.begin();
+    walk_scene(scene, root, view_projection_matrix, &mut |node, _| {
+        drawn_nodes.push(node);
+        gl::Uniform1ui(1, drawn_nodes.len() as u32);
+    });
+    let id = picking_buffer.read_id(cursor.0, cursor.1);
+    picking_buffer.end();
 
-    for &child in &node.children {
-        draw_scene(
-            &*child,
-            view_projection_matrix,
-            &(transformation_so_far * model_matrix),
-            camera_pos,
-        );
+    match id {
+        0 => None,
+        id => drawn_nodes.get(id as usize - 1).copied(),
     }
 }
 
-fn create_helicopter(
-) -> std::mem::ManuallyDrop<std::pin::Pin<std::boxed::Box<scene_graph::SceneNode>>> {
-    let helicopter_model = Helicopter::load("resources/helicopter.obj");
-    let helicopter_body_vao = unsafe { create_vao(&helicopter_model.body) };
-    let helicopter_door_vao = unsafe { create_vao(&helicopter_model.door) };
-    let helicopter_tail_vao = unsafe { create_vao(&helicopter_model.tail_rotor) };
-    let helicopter_main_rotor_vao = unsafe { create_vao(&helicopter_model.main_rotor) };
-    // Set up scene graph
-    let mut helicopter_body_node = SceneNode::from_vao(
-        helicopter_body_vao,
-        helicopter_model.body.indices.len() as i32,
-    );
-    let helicopter_door_node = SceneNode::from_vao(
-        helicopter_door_vao,
-        helicopter_model.door.indices.len() as i32,
-    );
-    let mut helicopter_tail_node = SceneNode::from_vao(
-        helicopter_tail_vao,
-        helicopter_model.tail_rotor.indices.len() as i32,
-    );
-    helicopter_tail_node.reference_point = glm::vec3(0.035_f32, 0.023_f32, 0.104_f32);
-    let helicopter_main_rotor_node = SceneNode::from_vao(
-        helicopter_main_rotor_vao,
-        helicopter_model.main_rotor.indices.len() as i32,
-    );
-    helicopter_body_node.add_child(&helicopter_main_rotor_node);
-    helicopter_body_node.add_child(&helicopter_tail_node);
-    helicopter_body_node.add_child(&helicopter_door_node);
+// Draw each view through its camera into its own part of the framebuffer, clearing only that
+// part. Remember to set the viewport back afterwards.
+unsafe fn draw_views(
+    scene: &SceneGraph,
+    root: NodeId,
+    views: &[(Rect, &Camera)],
+    clear_color: [f32; 4],
+) {
+    gl::Enable(gl::SCISSOR_TEST);
+    for (rect, camera) in views {
+        rect.apply();
+        camera.projection.set_depth_state();
+
+        // Clear the color and depth buffers
+        let [r, g, b, a] = clear_color;
+        gl::ClearColor(r, g, b, a);
+        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+
+        // Draw the scene
+        let transformation = camera.view_projection();
+        draw_scene(scene, root, &transformation, &camera.eye());
+    }
+    gl::Disable(gl::SCISSOR_TEST);
+}
 
-    helicopter_body_node
+// The OpenGL state everything is drawn with
+unsafe fn set_up_opengl() {
+    gl::Enable(gl::DEPTH_TEST);
+    gl::DepthFunc(gl::LESS);
+    gl::Enable(gl::CULL_FACE);
+    gl::Disable(gl::MULTISAMPLE);
+    gl::Enable(gl::BLEND);
+    gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+    gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+    gl::DebugMessageCallback(Some(util::debug_callback), ptr::null());
 }
 
 fn main() {
-    // Set up the necessary objects to deal with windows and event handling
-    let el = glutin::event_loop::EventLoop::new();
-    let wb = glutin::window::WindowBuilder::new()
-        .with_title("Gloom-rs")
-        .with_resizable(true)
-        .with_inner_size(glutin::dpi::LogicalSize::new(
-            INITIAL_SCREEN_W,
-            INITIAL_SCREEN_H,
-        ));
-    let cb = glutin::ContextBuilder::new().with_vsync(true);
-    let windowed_context = cb.build_windowed(wb, &el).unwrap();
-    // Uncomment these if you want to use the mouse for controls, but want it to be confined to the screen and/or invisible.
-    // windowed_context.window().set_cursor_grab(true).expect("failed to grab cursor");
-    // windowed_context.window().set_cursor_visible(false);
+    // Settings come from resources/config.ron and the command line, see `config.rs`
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help") {
+        println!("{}", config::USAGE);
+        return;
+    }
+    let config = Config::from_args(&args).unwrap_or_else(|e| {
+        println!("{}", e);
+        std::process::exit(2);
+    });
+
+    // Check the golden images with --golden, or make the current renders the new references
+    // with --golden --bless
+    if config.golden {
+        if let Err(e) = golden::run(config.bless) {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Set up the necessary objects to deal with windows and event handling, unless rendering
+    // headless and there is no window to deal with
+    let el = config
+        .headless
+        .is_none()
+        .then(glutin::event_loop::EventLoop::new);
+    let windowed_context = el.as_ref().map(|el| {
+        let fullscreen = config
+            .fullscreen
+            .then_some(glutin::window::Fullscreen::Borderless(None));
+        let wb = glutin::window::WindowBuilder::new()
+            .with_title("Gloom-rs")
+            .with_resizable(true)
+            .with_fullscreen(fullscreen)
+            .with_inner_size(glutin::dpi::LogicalSize::new(config.width, config.height));
+        let cb = glutin::ContextBuilder::new()
+            .with_vsync(config.vsync)
+            .with_multisampling(config.msaa);
+        cb.build_windowed(wb, el).unwrap()
+    });
+    // The cursor is grabbed and hidden for mouse-look from the render thread, which owns the
+    // window once it has made the context current
 
     // Set up a shared vector for keeping track of currently pressed keys
     let arc_pressed_keys = Arc::new(Mutex::new(Vec::<VirtualKeyCode>::with_capacity(10)));
@@ -261,34 +394,85 @@ fn main() {
     // Make a reference of this tuple to send to the render thread
     let mouse_delta = Arc::clone(&arc_mouse_delta);
 
+    // Set up shared value for tracking scroll wheel steps between frames
+    let arc_scroll = Arc::new(Mutex::new(0f32));
+    // Make a reference of this value to send to the render thread
+    let scroll = Arc::clone(&arc_scroll);
+
     // Set up shared tuple for tracking changes to the window size
-    let arc_window_size = Arc::new(Mutex::new((INITIAL_SCREEN_W, INITIAL_SCREEN_H, false)));
+    let arc_window_size = Arc::new(Mutex::new((config.width, config.height, false)));
     // Make a reference of this tuple to send to the render thread
     let window_size = Arc::clone(&arc_window_size);
 
+    // Set up a shared vector for keeping track of currently pressed mouse buttons
+    let arc_pressed_buttons = Arc::new(Mutex::new(Vec::<MouseButton>::with_capacity(3)));
+    // Make a reference of this vector to send to the render thread
+    let pressed_buttons = Arc::clone(&arc_pressed_buttons);
+
+    // Set up a shared vector for the mouse buttons pressed since the last frame, so a click
+    // that is over before the render thread looks still counts
+    let arc_clicked_buttons = Arc::new(Mutex::new(Vec::<MouseButton>::with_capacity(3)));
+    // Make a reference of this vector to send to the render thread
+    let clicked_buttons = Arc::clone(&arc_clicked_buttons);
+
+    // Set up shared tuple for the cursor position, used for picking
+    let arc_cursor_position = Arc::new(Mutex::new((0_u32, 0_u32)));
+    // Make a reference of this tuple to send to the render thread
+    let cursor_position = Arc::clone(&arc_cursor_position);
+
+    // The render thread asks the event loop to quit by sending it an event
+    let exit = el.as_ref().map(|el| el.create_proxy());
+    let quit = move || {
+        if let Some(exit) = &exit {
+            exit.send_event(()).ok();
+        }
+    };
+
+    // Lines typed into the terminal are commands for changing key bindings, see
+    // `Actions::run_command`
+    let (console_sender, console) = mpsc::channel::<String>();
+    thread::spawn(move || {
+        for line in std::io::stdin().lines().map_while(Result::ok) {
+            if console_sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
     // Spawn a separate thread for rendering, so event handling doesn't block rendering
     let render_thread = thread::spawn(move || {
         // Acquire the OpenGL Context and load the function pointers.
         // This has to be done inside of the rendering thread, because
         // an active OpenGL context cannot safely traverse a thread boundary
-        let context = unsafe {
-            let c = windowed_context.make_current().unwrap();
-            gl::load_with(|symbol| c.get_proc_address(symbol) as *const _);
-            c
+        let mut display = match windowed_context {
+            Some(windowed_context) => unsafe {
+                let c = windowed_context.make_current().unwrap();
+                gl::load_with(|symbol| c.get_proc_address(symbol) as *const _);
+                let sequence = config.sequence.as_ref().map(|output| {
+                    capture::Sequence::new(output).unwrap_or_else(|e| panic!("{}", e))
+                });
+                Display::Window {
+                    context: c,
+                    sequence,
+                }
+            },
+            None => Display::headless(
+                config.width,
+                config.height,
+                config.headless.as_deref().unwrap(),
+                config.frames,
+            )
+            .unwrap_or_else(|e| panic!("{}", e)),
         };
 
-        let mut window_aspect_ratio = INITIAL_SCREEN_W as f32 / INITIAL_SCREEN_H as f32;
+        let window_aspect_ratio = config.width as f32 / config.height as f32;
 
         // Set up openGL
         unsafe {
-            gl::Enable(gl::DEPTH_TEST);
-            gl::DepthFunc(gl::LESS);
-            gl::Enable(gl::CULL_FACE);
-            gl::Disable(gl::MULTISAMPLE);
-            gl::Enable(gl::BLEND);
-            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
-            gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
-            gl::DebugMessageCallback(Some(util::debug_callback), ptr::null());
+            set_up_opengl();
+            if config.msaa > 0 && config.headless.is_none() {
+                gl::Enable(gl::MULTISAMPLE);
+            }
 
             // Print some diagnostics
             println!(
@@ -303,197 +487,468 @@ fn main() {
             );
         }
 
-        // Position
-        let mut pitch = 0_f32;
-        let mut yaw = 0_f32;
-        let mut x = 0_f32;
-        let mut y = 0_f32;
-        let mut z = -3_f32;
-
         // Load models
 
-        let terrain_model = Terrain::load("resources/lunarsurface.obj");
-        let terrain_vao = unsafe { create_vao(&terrain_model) };
-
-        let mut helicopters = [
-            (create_helicopter(), glm::vec3(0_f32, 0_f32, 0_f32)),
-            (create_helicopter(), glm::vec3(10_f32, 20_f32, 40_f32)),
-            (create_helicopter(), glm::vec3(0_f32, 15_f32, 25_f32)),
-            (create_helicopter(), glm::vec3(0_f32, 10_f32, 30_f32)),
-            (create_helicopter(), glm::vec3(0_f32, 5_f32, -30_f32)),
-        ];
-        let mut terrain_node = SceneNode::from_vao(terrain_vao, terrain_model.indices.len() as i32);
-        helicopters
-            .iter()
-            .for_each(|h| terrain_node.add_child(&h.0));
+        let mut scene = SceneGraph::new();
+        let mut scene_description =
+            scene_file::load(&config.scene).unwrap_or_else(|e| panic!("{}", e));
+        // Leave out the helicopters past the count in the config
+        if let Some(count) = config.helicopters {
+            let mut kept = 0;
+            scene_description.root.children.retain(|child| {
+                if !child.name.starts_with("helicopter_") {
+                    return true;
+                }
+                kept += 1;
+                kept <= count
+            });
+        }
+        // Keep the mesh of the terrain on the CPU side as well, to collide with
+        let terrain_source = scene_description.root.mesh.clone();
+        let mut terrain_mesh = None;
+        let terrain_node = scene_description
+            .instantiate(&mut scene, &mut |source, mesh| {
+                if terrain_source.as_ref() == Some(source) {
+                    terrain_mesh = Some(mesh.clone());
+                }
+                unsafe { create_vao(mesh) }
+            })
+            .unwrap_or_else(|e| panic!("{}", e));
+        let clips = scene_description
+            .load_clips()
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        let terrain_collider = terrain_mesh
+            .map(|terrain| TerrainCollider::new(&terrain, &scene.world_matrix(terrain_node)))
+            .expect("The terrain has no mesh");
+        let mut world = World::new(
+            &scene,
+            terrain_node,
+            terrain_collider,
+            clips,
+            config.rotor_speed,
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+        let helicopter = world.helicopter;
+        // How far above the ground the camera stays
+        let camera_clearance = 0.05_f32;
 
         // == // Set up your shaders here
 
-        unsafe {
+        let simple_shader = unsafe {
             shader::ShaderBuilder::new()
                 .attach_file("./shaders/simple.frag")
                 .attach_file("./shaders/simple.vert")
                 .link()
-                .activate();
-        }
-
-        // Helicopter rotor rotation
+        };
+        unsafe { simple_shader.activate() };
+
+        let mut picking_buffer = unsafe { PickingBuffer::new(config.width, config.height) };
+
+        // Everything the user does goes through named actions
+        let bindings = Bindings::load(BINDINGS_PATH).unwrap_or_else(|e| {
+            println!("{}, using the default key bindings", e);
+            Bindings::default()
+        });
+        let mut actions = Actions::new(bindings);
+
+        let settings =
+            || Settings::new(&config, &actions.bindings).unwrap_or_else(|e| panic!("{}", e));
+        let mut recorder = config.record.as_ref().map(|path| {
+            println!("Recording input to {}", path);
+            Recorder::create(path, &settings()).unwrap_or_else(|e| panic!("{}", e))
+        });
+        let mut replay = config.replay.as_ref().map(|path| {
+            let replay = Replay::load(path, &settings()).unwrap_or_else(|e| panic!("{}", e));
+            println!("Replaying {} frames from {}", replay.len(), path);
+            replay
+        });
+
+        // The camera starts out flying freely, and switch_camera cycles through looking at the
+        // player's helicopter in the other modes
+        let mut camera = Camera::new(glm::vec3(0_f32, 0_f32, 3_f32), window_aspect_ratio);
+        let camera_modes = [
+            CameraMode::Fly,
+            CameraMode::Orbit(helicopter),
+            CameraMode::Chase(helicopter),
+            CameraMode::Cockpit(helicopter),
+        ];
 
-        let main_rotor_speed = 15_f32;
-        let tail_rotor_speed = 20_f32;
+        // The other views in the split-screen layouts follow the player's helicopter, one from
+        // behind and one as a map from above
+        let mut chase_camera = camera.clone();
+        chase_camera.set_mode(CameraMode::Chase(helicopter));
+        let mut map_camera = camera.clone();
+        map_camera.set_mode(CameraMode::Cockpit(helicopter));
+        map_camera.projection = Projection::TopDown;
+        let mut layout = Layout::Single;
+        let mut screen_size = (config.width, config.height);
+
+        // Looking around with the mouse only happens while grab_cursor has grabbed it, so it is
+        // still free for picking otherwise
+        let mut mouse_look = config.mouse_look();
+        let mut cursor_grabbed = false;
+
+        // Bookmarked camera poses, and the camera path being recorded or played back
+        let mut bookmarks = Bookmarks::load(BOOKMARKS_PATH).unwrap_or_else(|e| {
+            println!("{}", e);
+            Bookmarks::default()
+        });
+        let mut path_recorder: Option<PathRecorder> = None;
+        let mut cinematic: Option<Cinematic> = None;
+
+        // The world is simulated at a fixed rate of its own, and everything it moves is drawn
+        // in between its last two states
+        let mut clock: Box<dyn Clock> = if display.writes_frames() {
+            Box::new(simulation::SteppedClock::new(1.0 / 60.0))
+        } else {
+            Box::new(simulation::SystemClock::new())
+        };
 
         // The main rendering loop
-        let first_frame_time = std::time::Instant::now();
-        let mut previous_frame_time = first_frame_time;
+        loop {
+            // Stop once every frame there is to write has been written, before drawing any more
+            if display.finished() {
+                break;
+            }
 
-        let mut helicopter = create_helicopter();
-        terrain_node.add_child(&helicopter);
+            // Gather what the user did since the last frame, or take it from the recording.
+            // While replaying, the window is only looked at for closing it.
+            let frame = match replay.as_mut() {
+                Some(replay) => match replay.next_frame() {
+                    Some(frame) => frame,
+                    None => {
+                        // Print where things ended up, so runs can be compared
+                        println!(
+                            "Replay finished: camera at {:?}, helicopter at {:?}",
+                            camera.eye(),
+                            world.flight_model.state.position
+                        );
+                        quit();
+                        break;
+                    }
+                },
+                None => InputFrame {
+                    time: clock.now(),
+                    keys: pressed_keys
+                        .lock()
+                        .map(|keys| keys.clone())
+                        .unwrap_or_default(),
+                    buttons: pressed_buttons
+                        .lock()
+                        .map(|buttons| buttons.clone())
+                        .unwrap_or_default(),
+                    clicks: clicked_buttons
+                        .lock()
+                        .map(|mut clicks| mem::take(&mut *clicks))
+                        .unwrap_or_default(),
+                    // the x and y movement of the mouse since last frame in pixels
+                    mouse_delta: mouse_delta
+                        .lock()
+                        .map(|mut delta| mem::replace(&mut *delta, (0.0, 0.0)))
+                        .unwrap_or_default(),
+                    scroll: scroll
+                        .lock()
+                        .map(|mut steps| mem::replace(&mut *steps, 0.0))
+                        .unwrap_or_default(),
+                    cursor: cursor_position.lock().map_or((0, 0), |cursor| *cursor),
+                    cursor_grabbed,
+                    resize: window_size.lock().ok().and_then(|mut size| {
+                        let resized = size.2;
+                        size.2 = false;
+                        resized.then_some((size.0, size.1))
+                    }),
+                    console: console.try_iter().collect(),
+                },
+            };
+            if let Some(recorder) = recorder.as_mut() {
+                if let Err(e) = recorder.record(&frame) {
+                    println!("{}", e);
+                }
+            }
 
-        loop {
-            // Compute time passed since the previous frame and since the start of the program
-            let now = std::time::Instant::now();
-            let elapsed = now.duration_since(first_frame_time).as_secs_f32();
-            let delta_time = now.duration_since(previous_frame_time).as_secs_f32();
-            previous_frame_time = now;
-
-            // Camera transformation
-            let projection = glm::perspective(window_aspect_ratio, 81_f32, 0.1_f32, 100_f32);
-
-            let forward_t = glm::translate(&glm::identity::<f32, 4>(), &glm::vec3(0_f32, 0_f32, z));
-            let sideways_t =
-                glm::translate(&glm::identity::<f32, 4>(), &glm::vec3(x, 0_f32, 0_f32));
-            let up_t = glm::translate(&glm::identity::<f32, 4>(), &glm::vec3(0_f32, y, 0_f32));
-            let pitch_t = glm::rotation(pitch, &glm::vec3(1_f32, 0_f32, 0_f32));
-            let yaw_t = glm::rotation(yaw, &glm::vec3(0_f32, 1_f32, 0_f32));
-            let mirror_flip = glm::mat4(
-                // To fix correct back face
-                // culling.
-                -1_f32, 0_f32, 0_f32, 0_f32, 0_f32, -1_f32, 0_f32, 0_f32, 0_f32, 0_f32, 1_f32,
-                0_f32, 0_f32, 0_f32, 0_f32, 1_f32,
+            actions.update(
+                &frame.keys,
+                &frame.buttons,
+                &frame.clicks,
+                frame.mouse_delta,
+                frame.scroll,
             );
-            let camera_offset = glm::vec3(0_f32, 0.1_f32, -0.5_f32);
-            let transformation = projection
-                * glm::translate(&glm::identity::<f32, 4>(), &camera_offset)
-                * pitch_t
-                * yaw_t
-                * forward_t
-                * sideways_t
-                * up_t
-                * mirror_flip;
-
-            let helicopter_movement = toolbox::simple_heading_animation(elapsed);
-
-            helicopters.iter_mut().for_each(|h| {
-                h.0.rotation.z = helicopter_movement.roll;
-                h.0.rotation.y = helicopter_movement.yaw;
-                h.0.rotation.x = helicopter_movement.pitch;
-                h.0.position = 1_f32 / 100_f32
-                    * (h.1 + glm::vec3(helicopter_movement.x, 0_f32, helicopter_movement.z));
-                unsafe { (*h.0.children[0]).rotation.y = main_rotor_speed * elapsed };
-                unsafe { (*h.0.children[1]).rotation.x = tail_rotor_speed * elapsed };
-            });
+            for line in &frame.console {
+                let result = bookmarks
+                    .run_command(line, &camera, BOOKMARKS_PATH)
+                    .or_else(|| camera.run_command(line))
+                    .unwrap_or_else(|| actions.run_command(line, BINDINGS_PATH));
+                match result {
+                    Ok(message) | Err(message) => println!("{}", message),
+                }
+            }
+            if actions.pressed("quit") {
+                quit();
+                break;
+            }
 
-            unsafe { (*helicopter.children[0]).rotation.y = main_rotor_speed * elapsed };
-            unsafe { (*helicopter.children[1]).rotation.x = tail_rotor_speed * elapsed };
-            helicopter.rotation.y = yaw;
-            helicopter.rotation.x = pitch;
-            helicopter.position = glm::vec3(x, y, -z);
+            // Step the world as far as the time passed since the previous frame allows
+            let delta_time = world.update(&mut scene, &actions, frame.time);
 
             // Handle resize events
-            if let Ok(mut new_size) = window_size.lock() {
-                if new_size.2 {
-                    context.resize(glutin::dpi::PhysicalSize::new(new_size.0, new_size.1));
-                    window_aspect_ratio = new_size.0 as f32 / new_size.1 as f32;
-                    new_size.2 = false;
-                    println!("Window was resized to {}x{}", new_size.0, new_size.1);
-                    unsafe {
-                        gl::Viewport(0, 0, new_size.0 as i32, new_size.1 as i32);
+            if let Some((width, height)) = frame.resize {
+                display.resize(width, height);
+                screen_size = (width, height);
+                println!("Window was resized to {}x{}", width, height);
+                unsafe {
+                    gl::Viewport(0, 0, width as i32, height as i32);
+                    picking_buffer.resize(width, height);
+                }
+            }
+
+            // Grab and hide the cursor, or let it go again
+            if let Some(window) = display.window().filter(|_| actions.pressed("grab_cursor")) {
+                let grabbed = if cursor_grabbed {
+                    window.set_cursor_grab(CursorGrabMode::None)
+                } else {
+                    // Not every platform can confine the cursor, but those can lock it instead
+                    window
+                        .set_cursor_grab(CursorGrabMode::Confined)
+                        .or_else(|_| window.set_cursor_grab(CursorGrabMode::Locked))
+                };
+                match grabbed {
+                    Ok(()) => {
+                        cursor_grabbed = !cursor_grabbed;
+                        window.set_cursor_visible(!cursor_grabbed);
                     }
+                    Err(e) => println!("Could not grab the cursor: {}", e),
                 }
             }
 
-            // Handle keyboard input
-            let rotation_speed = 0.8_f32;
-            let movement_speed = 2_f32;
-
-            let view_rotation = glm::rotation(-yaw, &glm::vec3(0_f32, 1_f32, 0_f32))
-                * glm::rotation(-pitch, &glm::vec3(1_f32, 0_f32, 0_f32));
-            let forward = view_rotation * glm::vec4(0_f32, 0_f32, 1_f32, 1_f32);
-            let right = view_rotation * glm::vec4(1_f32, 0_f32, 0_f32, 1_f32);
-            if let Ok(keys) = pressed_keys.lock() {
-                for key in keys.iter() {
-                    match key {
-                        // The `VirtualKeyCode` enum is defined here:
-                        //    https://docs.rs/winit/0.25.0/winit/event/enum.VirtualKeyCode.html
-                        VirtualKeyCode::D => {
-                            x += delta_time * movement_speed * right.x;
-                            y += delta_time * movement_speed * right.y;
-                            z += delta_time * movement_speed * right.z;
-                        }
-                        VirtualKeyCode::A => {
-                            x -= delta_time * movement_speed * right.x;
-                            y -= delta_time * movement_speed * right.y;
-                            z -= delta_time * movement_speed * right.z;
-                        }
-                        VirtualKeyCode::W => {
-                            x += delta_time * movement_speed * forward.x;
-                            y += delta_time * movement_speed * forward.y;
-                            z += delta_time * movement_speed * forward.z;
-                        }
-                        VirtualKeyCode::S => {
-                            x -= delta_time * movement_speed * forward.x;
-                            y -= delta_time * movement_speed * forward.y;
-                            z -= delta_time * movement_speed * forward.z;
-                        }
-                        VirtualKeyCode::Space => y += delta_time * movement_speed,
-                        VirtualKeyCode::Left => yaw += delta_time * rotation_speed,
-                        VirtualKeyCode::Right => yaw -= delta_time * rotation_speed,
-                        VirtualKeyCode::LShift => y -= delta_time * movement_speed,
-                        VirtualKeyCode::Up => {
-                            pitch = f32::min(
-                                pitch + delta_time * rotation_speed,
-                                glm::pi::<f32>() / 2_f32,
-                            )
-                        }
-                        VirtualKeyCode::Down => {
-                            pitch = f32::max(
-                                pitch - delta_time * rotation_speed,
-                                -glm::pi::<f32>() / 2_f32,
-                            )
+            // Fly the camera with the keyboard and look around with the mouse, if the cursor
+            // was grabbed while it moved. The recording says so, since a replay may have no
+            // window to grab the cursor in.
+            camera.update_from_actions(&actions, &mut mouse_look, frame.cursor_grabbed, delta_time);
+
+            // Switch camera mode
+            if actions.pressed("switch_camera") {
+                let current = camera_modes
+                    .iter()
+                    .position(|&mode| mode == camera.mode())
+                    .unwrap_or(0);
+                camera.set_mode(camera_modes[(current + 1) % camera_modes.len()]);
+                println!("Camera mode: {:?}", camera.mode());
+            }
+
+            // Switch projection
+            if actions.pressed("switch_projection") {
+                let all = Projection::ALL;
+                let current = all
+                    .iter()
+                    .position(|&p| p == camera.projection)
+                    .unwrap_or(0);
+                camera.projection = all[(current + 1) % all.len()];
+                println!("Projection: {:?}", camera.projection);
+            }
+
+            // Switch split-screen layout
+            if actions.pressed("switch_layout") {
+                let all = Layout::ALL;
+                let current = all.iter().position(|&l| l == layout).unwrap_or(0);
+                layout = all[(current + 1) % all.len()];
+                println!("Layout: {:?}", layout);
+            }
+
+            // Fly to a bookmark, or store the camera there while set_bookmark is held
+            for number in 1..=9 {
+                if !actions.pressed(&format!("bookmark_{}", number)) {
+                    continue;
+                }
+                if actions.held("set_bookmark") {
+                    let name = format!("Bookmark {}", number);
+                    let name = bookmarks
+                        .bookmarks
+                        .get(&number)
+                        .map_or(name, |b| b.name.clone());
+                    match bookmarks.store(number, &name, &camera, BOOKMARKS_PATH) {
+                        Ok(message) | Err(message) => println!("{}", message),
+                    }
+                } else if let Some(bookmark) = bookmarks.bookmarks.get(&number) {
+                    let key = &bookmark.key;
+                    camera.fly_to(key.position(), key.yaw, key.pitch);
+                    println!("Flying to {}", bookmark.name);
+                }
+            }
+
+            // Start or stop recording the camera path
+            if actions.pressed("record_camera_path") {
+                match path_recorder.take() {
+                    Some(recorder) => {
+                        let path = recorder.finish(&camera);
+                        match path.save(CAMERA_PATH_PATH) {
+                            Ok(()) => println!(
+                                "Saved a camera path of {} keys to {}",
+                                path.keys.len(),
+                                CAMERA_PATH_PATH
+                            ),
+                            Err(e) => println!("{}", e),
                         }
+                    }
+                    None => {
+                        println!("Recording the camera path");
+                        path_recorder = Some(PathRecorder::default());
+                    }
+                }
+            }
+            if let Some(recorder) = path_recorder.as_mut() {
+                recorder.record(&camera, delta_time);
+            }
 
-                        // default handler:
-                        _ => {}
+            // Start or stop playing the camera path back. Switching camera mode stops it too.
+            if actions.pressed("play_camera_path") && cinematic.take().is_none() {
+                match CameraPath::load(CAMERA_PATH_PATH).and_then(|path| Cinematic::new(&path)) {
+                    Ok(playback) => {
+                        playback.start(&mut camera);
+                        cinematic = Some(playback);
                     }
+                    Err(e) => println!("{}", e),
+                }
+            }
+            if let Some(playback) = cinematic.as_mut() {
+                if camera.mode() != CameraMode::Fly || !playback.advance(&mut camera, delta_time) {
+                    println!("Camera path finished");
+                    cinematic = None;
+                }
+            }
+
+            // Lay the views out over the window, fitting each camera to its own view
+            let views = layout.views(screen_size.0, screen_size.1);
+            for (view, rect) in &views {
+                match view {
+                    View::Main => camera.aspect = rect.aspect(),
+                    View::Chase => chase_camera.aspect = rect.aspect(),
+                    View::Map => map_camera.aspect = rect.aspect(),
+                }
+            }
+
+            // Move the cameras to where their modes put them, keeping them out of the ground
+            for camera in [&mut camera, &mut chase_camera, &mut map_camera].iter_mut() {
+                camera.update(&scene, delta_time);
+                let eye = camera.eye();
+                if let Some((height, _)) = world.terrain.ground(eye.x, eye.z) {
+                    camera.keep_above(height + camera_clearance);
+                }
+            }
+
+            // Save the scene as it looks right now
+            if actions.pressed("save_scene") {
+                let description = scene_file::SceneDescription::from_scene(
+                    &scene,
+                    terrain_node,
+                    &scene_description,
+                );
+                match scene_file::save(SAVED_SCENE_PATH, &description) {
+                    Ok(()) => println!("Saved the scene to {}", SAVED_SCENE_PATH),
+                    Err(e) => println!("{}", e),
                 }
             }
-            // Handle mouse movement. delta contains the x and y movement of the mouse since last frame in pixels
-            if let Ok(mut delta) = mouse_delta.lock() {
-                // == // Optionally access the accumulated mouse movement between
-                // == // frames here with `delta.0` and `delta.1`
 
-                *delta = (0.0, 0.0); // reset when done
+            // Handle mouse clicks by picking the node under the cursor in the main view. The
+            // picking buffer covers the whole window, so the main view is stretched over it.
+            let main_rect = views
+                .iter()
+                .find(|(view, _)| *view == View::Main)
+                .unwrap()
+                .1;
+            let cursor = frame.cursor;
+            match main_rect.stretch_cursor(cursor, screen_size) {
+                Some(stretched) if actions.pressed("pick") => unsafe {
+                    // Before anything clears a depth buffer, since reversed depth clears to 0
+                    camera.projection.set_depth_state();
+                    match pick_scene(
+                        &picking_buffer,
+                        &scene,
+                        terrain_node,
+                        &camera.view_projection(),
+                        stretched,
+                    ) {
+                        Some(node) => scene[node].print(),
+                        None => println!("Nothing was picked at {}x{}", cursor.0, cursor.1),
+                    }
+                    simple_shader.activate();
+                },
+                None if actions.pressed("pick") => println!("Only the main view can be picked in"),
+                _ => {}
             }
 
             // == // Please compute camera transforms here (exercise 2 & 3)
 
+            let view_camera = |view: &View| match view {
+                View::Main => &camera,
+                View::Chase => &chase_camera,
+                View::Map => &map_camera,
+            };
+            let cameras: Vec<(Rect, &Camera)> = views
+                .iter()
+                .map(|(view, rect)| (*rect, view_camera(view)))
+                .collect();
             unsafe {
-                // Clear the color and depth buffers
-                gl::ClearColor(0.035, 0.046, 0.078, 1.0); // night sky
-                gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+                display.bind();
+                draw_views(&scene, terrain_node, &cameras, config.clear_color);
+                gl::Viewport(0, 0, screen_size.0 as i32, screen_size.1 as i32);
+            }
 
-                // Draw the scene
+            // Save what was just drawn
+            if actions.pressed("screenshot") {
+                let image = unsafe { display.read_image() };
+                match capture::save_screenshot(&image, capture::SCREENSHOT_DIRECTORY) {
+                    Ok(path) => println!("Saved a screenshot to {}", path),
+                    Err(e) => println!("{}", e),
+                }
+            }
 
-                draw_scene(
-                    &terrain_node,
-                    &transformation,
-                    &glm::identity::<f32, 4>(),
-                    &(camera_offset + glm::vec3(x, y, z)),
+            // Draw the frame again, larger than the window and with several samples for every
+            // pixel, and save that
+            if actions.pressed("high_res_screenshot") {
+                // Give up samples first and then size until it fits in a framebuffer
+                let max_size = unsafe { Framebuffer::max_size() };
+                let largest = screen_size.0.max(screen_size.1);
+                let (mut scale, mut supersampling) = (SCREENSHOT_SCALE, SCREENSHOT_SUPERSAMPLING);
+                while largest * scale * supersampling > max_size && supersampling > 1 {
+                    supersampling -= 1;
+                }
+                while largest * scale * supersampling > max_size && scale > 1 {
+                    scale -= 1;
+                }
+                let (width, height) = (
+                    screen_size.0 * scale * supersampling,
+                    screen_size.1 * scale * supersampling,
                 );
+                let large_views = layout.views(width, height);
+                let cameras: Vec<(Rect, &Camera)> = large_views
+                    .iter()
+                    .map(|(view, rect)| (*rect, view_camera(view)))
+                    .collect();
+                let image = unsafe {
+                    let image = Framebuffer::new(width, height).map(|target| {
+                        target.bind();
+                        draw_views(&scene, terrain_node, &cameras, config.clear_color);
+                        target.read_image()
+                    });
+                    display.bind();
+                    gl::Viewport(0, 0, screen_size.0 as i32, screen_size.1 as i32);
+                    image
+                };
+                let saved = image.and_then(|image| {
+                    let image = capture::downsample(&image, supersampling);
+                    capture::save_screenshot(&image, capture::SCREENSHOT_DIRECTORY)
+                });
+                match saved {
+                    Ok(path) => println!("Saved a high resolution screenshot to {}", path),
+                    Err(e) => println!("{}", e),
+                }
             }
 
-            // Display the new color buffer on the display
-            context.swap_buffers().unwrap(); // we use "double buffering" to avoid artifacts
+            // Display the new color buffer on the display, or write it out when headless
+            if let Err(e) = display.present() {
+                panic!("{}", e);
+            }
         }
     });
 
@@ -501,6 +956,17 @@ fn main() {
     // == // From here on down there are only internals.
     // == //
 
+    // Without a window there are no events to handle, so only wait for the frames to be drawn
+    let el = match el {
+        Some(el) => el,
+        None => {
+            if render_thread.join().is_err() {
+                std::process::exit(1);
+            }
+            return;
+        }
+    };
+
     // Keep track of the health of the rendering thread
     let render_thread_healthy = Arc::new(RwLock::new(true));
     let render_thread_watchdog = Arc::clone(&render_thread_healthy);
@@ -543,6 +1009,10 @@ fn main() {
             } => {
                 *control_flow = ControlFlow::Exit;
             }
+            // The quit action was triggered
+            Event::UserEvent(()) => {
+                *control_flow = ControlFlow::Exit;
+            }
             // Keep track of currently pressed keys to send to the rendering thread
             Event::WindowEvent {
                 event:
@@ -572,16 +1042,47 @@ fn main() {
                         }
                     }
                 }
-
-                // Handle Escape and Q keys separately
-                match keycode {
-                    Escape => {
-                        *control_flow = ControlFlow::Exit;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::CursorMoved { position, .. },
+                ..
+            } => {
+                if let Ok(mut cursor) = arc_cursor_position.lock() {
+                    *cursor = (position.x.max(0.0) as u32, position.y.max(0.0) as u32);
+                }
+            }
+            // Keep track of currently pressed mouse buttons as well
+            Event::WindowEvent {
+                event: WindowEvent::MouseInput { state, button, .. },
+                ..
+            } => {
+                if let Ok(mut buttons) = arc_pressed_buttons.lock() {
+                    match state {
+                        Released => buttons.retain(|&b| b != button),
+                        Pressed => {
+                            if !buttons.contains(&button) {
+                                buttons.push(button);
+                            }
+                        }
                     }
-                    Q => {
-                        *control_flow = ControlFlow::Exit;
+                }
+                if let (Pressed, Ok(mut clicks)) = (state, arc_clicked_buttons.lock()) {
+                    if !clicks.contains(&button) {
+                        clicks.push(button);
                     }
-                    _ => {}
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::MouseWheel { delta, .. },
+                ..
+            } => {
+                // Touchpads scroll by pixels rather than steps
+                let steps = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 50.0,
+                };
+                if let Ok(mut scroll) = arc_scroll.lock() {
+                    *scroll += steps;
                 }
             }
             Event::DeviceEvent {
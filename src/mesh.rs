@@ -1,3 +1,7 @@
+extern crate nalgebra_glm as glm;
+
+use serde::{Deserialize, Serialize};
+
 // internal helper
 fn generate_color_vec(color: [f32; 4], num: usize) -> Vec<f32> {
     color.iter().cloned().cycle().take(num * 4).collect()
@@ -5,6 +9,7 @@ fn generate_color_vec(color: [f32; 4], num: usize) -> Vec<f32> {
 
 // Mesh
 
+#[derive(Clone)]
 pub struct Mesh {
     pub vertices: Vec<f32>,
     pub normals: Vec<f32>,
@@ -24,125 +29,201 @@ impl Mesh {
     }
 }
 
-// Lunar terrain
-
-pub struct Terrain;
-impl Terrain {
-    pub fn load(path: &str) -> Mesh {
-        println!("Loading terrain model...");
-        let before = std::time::Instant::now();
-        let (models, _materials) = tobj::load_obj(
-            path,
-            &tobj::LoadOptions {
-                triangulate: true,
-                single_index: true,
-                ..Default::default()
-            },
-        )
-        .expect("Failed to load terrain model");
-        let after = std::time::Instant::now();
-        println!(
-            "Done in {:.3}ms.",
-            after.duration_since(before).as_micros() as f32 / 1e3
-        );
-
-        if models.len() > 1 || models.is_empty() {
-            panic!("Please use a model with a single mesh!")
-            // You could try merging the vertices and indices
-            // of the separate meshes into a single mesh.
-            // I'll leave that as an optional exercise. ;)
-        }
+// An axis aligned box around a mesh. Vertex positions are divided by 100 in the vertex shader,
+// so the box is too, to put it in the same units as the scene nodes.
 
-        let terrain = models[0].to_owned();
-        println!(
-            "Loaded {} with {} points and {} triangles.",
-            terrain.name,
-            terrain.mesh.positions.len() / 3,
-            terrain.mesh.indices.len() / 3,
-        );
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingBox {
+    pub min: glm::Vec3,
+    pub max: glm::Vec3,
+}
 
-        Mesh::from(terrain.mesh, [1.0, 1.0, 1.0, 1.0])
+impl BoundingBox {
+    pub fn corners(&self) -> [glm::Vec3; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            glm::vec3(a.x, a.y, a.z),
+            glm::vec3(b.x, a.y, a.z),
+            glm::vec3(a.x, b.y, a.z),
+            glm::vec3(b.x, b.y, a.z),
+            glm::vec3(a.x, a.y, b.z),
+            glm::vec3(b.x, a.y, b.z),
+            glm::vec3(a.x, b.y, b.z),
+            glm::vec3(b.x, b.y, b.z),
+        ]
     }
 }
 
-// Helicopter
+impl Mesh {
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        let mut points = self
+            .vertices
+            .chunks(3)
+            .map(|v| glm::vec3(v[0], v[1], v[2]) / 100_f32);
+        let first = points.next()?;
+        Some(points.fold(
+            BoundingBox {
+                min: first,
+                max: first,
+            },
+            |bounds, point| BoundingBox {
+                min: glm::min2(&bounds.min, &point),
+                max: glm::max2(&bounds.max, &point),
+            },
+        ))
+    }
+}
+
+// Where the mesh of a scene node came from, so scenes can be saved and loaded again
 
-pub struct Helicopter {
-    pub body: Mesh,
-    pub door: Mesh,
-    pub main_rotor: Mesh,
-    pub tail_rotor: Mesh,
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MeshSource {
+    pub path: String, // The OBJ file to load
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object: Option<String>, // Which object in the file to use, needed if it has several
+    #[serde(default = "white")]
+    pub color: [f32; 4],
 }
 
-// You can use square brackets to access the components of the helicopter, if you want to use loops!
-use std::ops::Index;
-impl Index<usize> for Helicopter {
-    type Output = Mesh;
-    fn index(&self, i: usize) -> &Mesh {
-        match i {
-            0 => &self.body,
-            1 => &self.main_rotor,
-            2 => &self.tail_rotor,
-            3 => &self.door,
-            _ => panic!("Invalid index, try [0,3]"),
-        }
+fn white() -> [f32; 4] {
+    [1.0, 1.0, 1.0, 1.0]
+}
+
+impl Mesh {
+    // Load the mesh a `MeshSource` describes. Use `load_obj_models` to read the file only once
+    // when taking several objects from it.
+    pub fn from_source(models: &[tobj::Model], source: &MeshSource) -> Result<Self, String> {
+        let model = match &source.object {
+            Some(name) => models
+                .iter()
+                .find(|m| &m.name == name)
+                .ok_or_else(|| format!("{} has no object named {}", source.path, name))?,
+            None if models.len() == 1 => &models[0],
+            None => {
+                return Err(format!(
+                    "{} has {} objects, please say which one to use",
+                    source.path,
+                    models.len()
+                ))
+            }
+        };
+        Ok(Mesh::from(model.mesh.clone(), source.color))
     }
 }
 
-impl Helicopter {
-    pub fn load(path: &str) -> Self {
-        println!("Loading helicopter model...");
-        let before = std::time::Instant::now();
-        let (models, _materials) = tobj::load_obj(
-            path,
-            &tobj::LoadOptions {
-                triangulate: true,
-                single_index: true,
-                ..Default::default()
-            },
-        )
-        .expect("Failed to load helicopter model");
-        let after = std::time::Instant::now();
-        println!(
-            "Done in {:.3}ms!",
-            after.duration_since(before).as_micros() as f32 / 1e3
-        );
-
-        for model in &models {
-            println!(
-                "Loaded {} with {} points and {} triangles.",
-                model.name,
-                model.mesh.positions.len() / 3,
-                model.mesh.indices.len() / 3
-            );
+pub fn load_obj_models(path: &str) -> Result<Vec<tobj::Model>, String> {
+    println!("Loading {}...", path);
+    let before = std::time::Instant::now();
+    let (models, _materials) = tobj::load_obj(
+        path,
+        &tobj::LoadOptions {
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        },
+    )
+    .map_err(|e| format!("Failed to load {}: {}", path, e))?;
+    let after = std::time::Instant::now();
+    println!(
+        "Done in {:.3}ms.",
+        after.duration_since(before).as_micros() as f32 / 1e3
+    );
+    Ok(models)
+}
+
+// Procedural primitives, with sizes in the same units as the OBJ files
+
+impl Mesh {
+    // A cube with sides of length `size` around the origin
+    pub fn cube(size: f32, color: [f32; 4]) -> Mesh {
+        let mut mesh = Mesh::empty();
+        let axes = [
+            glm::vec3(1.0, 0.0, 0.0),
+            glm::vec3(0.0, 1.0, 0.0),
+            glm::vec3(0.0, 0.0, 1.0),
+        ];
+        for (i, axis) in axes.iter().enumerate() {
+            for &sign in &[1_f32, -1_f32] {
+                // Two edges of the face, turned so the corners go around it counter-clockwise
+                let normal = sign * axis;
+                let u = sign * axes[(i + 1) % 3];
+                let v = axes[(i + 2) % 3];
+                let half = size / 2.0;
+                let corners = [(-1_f32, -1_f32), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+                let first = mesh.vertex_count();
+                for &(a, b) in corners.iter() {
+                    mesh.push_vertex(&(half * (normal + a * u + b * v)), &normal);
+                }
+                mesh.indices
+                    .extend([0, 1, 2, 0, 2, 3].iter().map(|i| first + i));
+            }
+        }
+        mesh.colors = generate_color_vec(color, mesh.vertex_count() as usize);
+        mesh
+    }
+
+    // A sphere around the origin, made of `slices` around the vertical axis and `stacks` from
+    // the bottom to the top
+    pub fn sphere(radius: f32, slices: u32, stacks: u32, color: [f32; 4]) -> Mesh {
+        let mut mesh = Mesh::empty();
+        for stack in 0..=stacks {
+            let polar = std::f32::consts::PI * stack as f32 / stacks as f32;
+            for slice in 0..=slices {
+                let azimuth = 2.0 * std::f32::consts::PI * slice as f32 / slices as f32;
+                let normal = glm::vec3(
+                    polar.sin() * azimuth.sin(),
+                    -polar.cos(),
+                    polar.sin() * azimuth.cos(),
+                );
+                mesh.push_vertex(&(radius * normal), &normal);
+            }
+        }
+        for stack in 0..stacks {
+            for slice in 0..slices {
+                let below = stack * (slices + 1) + slice;
+                let above = below + slices + 1;
+                mesh.indices.extend_from_slice(&[
+                    below,
+                    below + 1,
+                    above + 1,
+                    below,
+                    above + 1,
+                    above,
+                ]);
+            }
+        }
+        mesh.colors = generate_color_vec(color, mesh.vertex_count() as usize);
+        mesh
+    }
+
+    // A square in the XZ plane with sides of length `size`, facing up
+    pub fn plane(size: f32, color: [f32; 4]) -> Mesh {
+        let mut mesh = Mesh::empty();
+        let up = glm::vec3(0.0, 1.0, 0.0);
+        let half = size / 2.0;
+        for (x, z) in [(-1.0, 1.0), (1.0, 1.0), (1.0, -1.0), (-1.0, -1.0)].iter() {
+            mesh.push_vertex(&glm::vec3(half * x, 0.0, half * z), &up);
         }
+        mesh.indices = vec![0, 1, 2, 0, 2, 3];
+        mesh.colors = generate_color_vec(color, 4);
+        mesh
+    }
 
-        let body_model = models
-            .iter()
-            .find(|m| m.name == "Body_body")
-            .expect("Incorrect model file!")
-            .to_owned();
-        let door_model = models
-            .iter()
-            .find(|m| m.name == "Door_door")
-            .expect("Incorrect model file!")
-            .to_owned();
-        let main_rotor_model = models
-            .iter()
-            .find(|m| m.name == "Main_Rotor_main_rotor")
-            .expect("Incorrect model file!")
-            .to_owned();
-        let tail_rotor_model = models
-            .iter()
-            .find(|m| m.name == "Tail_Rotor_tail_rotor")
-            .expect("Incorrect model file!")
-            .to_owned();
-
-        Helicopter {
-            body: Mesh::from(body_model.mesh, [0.3, 0.3, 0.3, 1.0]),
-            door: Mesh::from(door_model.mesh, [0.1, 0.1, 0.3, 1.0]),
-            main_rotor: Mesh::from(main_rotor_model.mesh, [0.3, 0.1, 0.1, 1.0]),
-            tail_rotor: Mesh::from(tail_rotor_model.mesh, [0.1, 0.3, 0.1, 1.0]),
+    fn empty() -> Mesh {
+        Mesh {
+            vertices: vec![],
+            normals: vec![],
+            colors: vec![],
+            indices: vec![],
         }
     }
+
+    fn vertex_count(&self) -> u32 {
+        (self.vertices.len() / 3) as u32
+    }
+
+    fn push_vertex(&mut self, position: &glm::Vec3, normal: &glm::Vec3) {
+        self.vertices.extend_from_slice(position.as_slice());
+        self.normals.extend_from_slice(normal.as_slice());
+    }
 }
@@ -0,0 +1,379 @@
+extern crate nalgebra_glm as glm;
+
+use crate::toolbox::{self, Heading};
+
+// Smooth paths through a list of control points, for things to fly along.
+//
+// Every curve is made of cubic segments, each shaped by four control points, and is evaluated
+// at a parameter running from 0 to the number of segments. Since that parameter does not move
+// at a constant speed along the curve, a table of arc lengths is kept so the path can also be
+// followed by distance travelled.
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CurveKind {
+    // Passes through every control point
+    CatmullRom,
+    // Passes through every third point, with the two in between pulling the curve towards them
+    Bezier,
+    // Uniform cubic B-spline, smoother than Catmull-Rom but only passes near the points
+    BSpline,
+}
+
+// Samples per segment in the arc length table
+const LENGTH_SAMPLES: usize = 64;
+
+#[derive(Clone, Debug)]
+pub struct Path {
+    kind: CurveKind,
+    points: Vec<glm::Vec3>,
+    closed: bool,
+    // Distance along the path at every sample, LENGTH_SAMPLES samples per segment
+    lengths: Vec<f32>,
+}
+
+impl Path {
+    // A closed path loops from the end back to its start. Bezier paths need 3n + 1 points when
+    // open and 3n when closed, while the others need at least four (or two for Catmull-Rom).
+    pub fn new(kind: CurveKind, points: Vec<glm::Vec3>, closed: bool) -> Result<Path, String> {
+        let n = points.len();
+        let valid = match (kind, closed) {
+            (CurveKind::CatmullRom, false) => n >= 2,
+            (CurveKind::CatmullRom, true) => n >= 3,
+            (CurveKind::Bezier, false) => n >= 4 && n % 3 == 1,
+            (CurveKind::Bezier, true) => n >= 3 && n.is_multiple_of(3),
+            (CurveKind::BSpline, _) => n >= 4,
+        };
+        if !valid {
+            return Err(format!(
+                "{} control points can not make {} {:?} path",
+                n,
+                if closed { "a closed" } else { "an open" },
+                kind
+            ));
+        }
+
+        let mut path = Path {
+            kind,
+            points,
+            closed,
+            lengths: vec![],
+        };
+        path.measure();
+        Ok(path)
+    }
+
+    pub fn segments(&self) -> usize {
+        let n = self.points.len();
+        match (self.kind, self.closed) {
+            (CurveKind::CatmullRom, false) => n - 1,
+            (CurveKind::Bezier, false) => (n - 1) / 3,
+            (CurveKind::Bezier, true) => n / 3,
+            (CurveKind::BSpline, false) => n - 3,
+            (_, true) => n,
+        }
+    }
+
+    pub fn length(&self) -> f32 {
+        *self.lengths.last().unwrap()
+    }
+
+    // Position on the curve, for a parameter between 0 and `segments()`
+    pub fn point(&self, u: f32) -> glm::Vec3 {
+        self.evaluate(u, 0)
+    }
+
+    // First derivative with respect to the parameter, pointing along the curve
+    pub fn derivative(&self, u: f32) -> glm::Vec3 {
+        self.evaluate(u, 1)
+    }
+
+    pub fn second_derivative(&self, u: f32) -> glm::Vec3 {
+        self.evaluate(u, 2)
+    }
+
+    // The parameter reached after travelling `distance` from the start. Closed paths wrap
+    // around, open ones stop at their ends.
+    pub fn parameter_at_distance(&self, distance: f32) -> f32 {
+        let length = self.length();
+        let distance = if self.closed && length > 0.0 {
+            distance.rem_euclid(length)
+        } else {
+            distance.clamp(0.0, length)
+        };
+
+        let next = self
+            .lengths
+            .partition_point(|&l| l <= distance)
+            .clamp(1, self.lengths.len() - 1);
+        let (before, after) = (self.lengths[next - 1], self.lengths[next]);
+        let fraction = if after > before {
+            (distance - before) / (after - before)
+        } else {
+            0.0
+        };
+        (next - 1) as f32 / LENGTH_SAMPLES as f32 + fraction / LENGTH_SAMPLES as f32
+    }
+
+    pub fn point_at_distance(&self, distance: f32) -> glm::Vec3 {
+        self.point(self.parameter_at_distance(distance))
+    }
+
+    // The velocity of something moving along the path at a constant `speed`
+    pub fn velocity_at_distance(&self, distance: f32, speed: f32) -> glm::Vec3 {
+        glm::normalize(&self.derivative(self.parameter_at_distance(distance))) * speed
+    }
+
+    // Where something moving along the path at a constant `speed` is after `distance`, and
+    // which way it faces
+    #[allow(dead_code)]
+    pub fn heading_at_distance(&self, distance: f32, speed: f32) -> Heading {
+        let u = self.parameter_at_distance(distance);
+        let derivative = self.derivative(u);
+        let second_derivative = self.second_derivative(u);
+
+        // Moving at a constant speed, all acceleration is the curve pulling sideways
+        let forward = glm::normalize(&derivative);
+        let curvature = (second_derivative - glm::dot(&second_derivative, &forward) * forward)
+            / glm::length2(&derivative);
+        toolbox::heading_from_motion(
+            &self.point(u),
+            &(forward * speed),
+            &(curvature * speed * speed),
+        )
+    }
+
+    // Derivative `order` (0 to 2) of the curve at parameter `u`
+    fn evaluate(&self, u: f32, order: usize) -> glm::Vec3 {
+        let segments = self.segments();
+        let u = u.clamp(0.0, segments as f32);
+        let segment = usize::min(u as usize, segments - 1);
+        let t = u - segment as f32;
+
+        let powers = match order {
+            0 => glm::vec4(1.0, t, t * t, t * t * t),
+            1 => glm::vec4(0.0, 1.0, 2.0 * t, 3.0 * t * t),
+            _ => glm::vec4(0.0, 0.0, 2.0, 6.0 * t),
+        };
+        let weights = self.basis().transpose() * powers;
+        let points = self.segment_points(segment);
+        (0..4).fold(glm::zero(), |sum: glm::Vec3, i| {
+            sum + weights[i] * points[i]
+        })
+    }
+
+    // Rows are multiplied by 1, t, t^2 and t^3 respectively
+    fn basis(&self) -> glm::Mat4 {
+        match self.kind {
+            CurveKind::CatmullRom => {
+                glm::mat4(
+                    0.0, 2.0, 0.0, 0.0, //
+                    -1.0, 0.0, 1.0, 0.0, //
+                    2.0, -5.0, 4.0, -1.0, //
+                    -1.0, 3.0, -3.0, 1.0,
+                ) / 2.0
+            }
+            CurveKind::Bezier => glm::mat4(
+                1.0, 0.0, 0.0, 0.0, //
+                -3.0, 3.0, 0.0, 0.0, //
+                3.0, -6.0, 3.0, 0.0, //
+                -1.0, 3.0, -3.0, 1.0,
+            ),
+            CurveKind::BSpline => {
+                glm::mat4(
+                    1.0, 4.0, 1.0, 0.0, //
+                    -3.0, 0.0, 3.0, 0.0, //
+                    3.0, -6.0, 3.0, 0.0, //
+                    -1.0, 3.0, -3.0, 1.0,
+                ) / 6.0
+            }
+        }
+    }
+
+    // The four control points shaping a segment
+    fn segment_points(&self, segment: usize) -> [glm::Vec3; 4] {
+        let n = self.points.len() as isize;
+        let first = match self.kind {
+            CurveKind::CatmullRom => segment as isize - 1,
+            CurveKind::Bezier => 3 * segment as isize,
+            CurveKind::BSpline => segment as isize,
+        };
+        let point = |i: isize| {
+            if self.closed {
+                self.points[i.rem_euclid(n) as usize]
+            } else {
+                // Only Catmull-Rom reaches past the ends, where the end points are repeated
+                self.points[i.clamp(0, n - 1) as usize]
+            }
+        };
+        [
+            point(first),
+            point(first + 1),
+            point(first + 2),
+            point(first + 3),
+        ]
+    }
+
+    fn measure(&mut self) {
+        let samples = self.segments() * LENGTH_SAMPLES;
+        let mut lengths = Vec::with_capacity(samples + 1);
+        let mut length = 0_f32;
+        let mut previous = self.point(0.0);
+        lengths.push(0.0);
+        for i in 1..=samples {
+            let point = self.point(i as f32 / LENGTH_SAMPLES as f32);
+            length += glm::distance(&previous, &point);
+            lengths.push(length);
+            previous = point;
+        }
+        self.lengths = lengths;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: &glm::Vec3, b: &glm::Vec3, tolerance: f32) -> bool {
+        glm::distance(a, b) < tolerance
+    }
+
+    // Unevenly spaced points, so the curve parameter does not move at a constant speed
+    fn uneven_points() -> Vec<glm::Vec3> {
+        vec![
+            glm::vec3(0.0, 0.0, 0.0),
+            glm::vec3(1.0, 0.0, 0.0),
+            glm::vec3(4.0, 1.0, 1.0),
+            glm::vec3(6.0, 0.0, 4.0),
+            glm::vec3(5.0, -1.0, 7.0),
+            glm::vec3(2.0, 0.0, 8.0),
+        ]
+    }
+
+    #[test]
+    fn equal_distances_make_equal_steps() {
+        for kind in [CurveKind::CatmullRom, CurveKind::BSpline] {
+            let path = Path::new(kind, uneven_points(), false).unwrap();
+            let steps = 200;
+            let step = path.length() / steps as f32;
+            let chords: Vec<f32> = (0..steps)
+                .map(|i| {
+                    let a = path.point_at_distance(i as f32 * step);
+                    let b = path.point_at_distance((i + 1) as f32 * step);
+                    glm::distance(&a, &b)
+                })
+                .collect();
+            // Chords are a little shorter than the arcs where the curve bends
+            for chord in chords {
+                assert!(
+                    (chord - step).abs() < 0.01 * step,
+                    "{:?}: {} != {}",
+                    kind,
+                    chord,
+                    step
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn closed_paths_wrap_around() {
+        let path = Path::new(CurveKind::CatmullRom, uneven_points(), true).unwrap();
+        let length = path.length();
+        assert!(close(
+            &path.point(path.segments() as f32),
+            &path.point(0.0),
+            1e-5
+        ));
+        for distance in [0.0, 1.5, 0.3 * length] {
+            let point = path.point_at_distance(distance);
+            assert!(close(
+                &path.point_at_distance(distance + length),
+                &point,
+                1e-3
+            ));
+            assert!(close(
+                &path.point_at_distance(distance - 2.0 * length),
+                &point,
+                1e-3
+            ));
+        }
+        // Across the seam the direction carries on smoothly
+        let before = path.velocity_at_distance(length - 0.01, 1.0);
+        let after = path.velocity_at_distance(0.01, 1.0);
+        assert!(glm::dot(&before, &after) > 0.99);
+
+        // Open paths stop at their ends instead
+        let open = Path::new(CurveKind::CatmullRom, uneven_points(), false).unwrap();
+        let end = open.point_at_distance(open.length());
+        assert!(close(
+            &open.point_at_distance(open.length() + 5.0),
+            &end,
+            1e-6
+        ));
+        assert!(close(&open.point_at_distance(-5.0), &glm::zero(), 1e-6));
+    }
+
+    #[test]
+    fn straight_paths_do_not_roll() {
+        let points = (0..5)
+            .map(|i| glm::vec3(2.0, 0.5, 0.0) * i as f32 + glm::vec3(0.0, 0.0, i as f32))
+            .collect();
+        let path = Path::new(CurveKind::CatmullRom, points, false).unwrap();
+        for i in 0..10 {
+            let heading = path.heading_at_distance(path.length() * i as f32 / 10.0, 30.0);
+            assert!(heading.roll.abs() < 1e-4, "{}", heading.roll);
+        }
+    }
+
+    #[test]
+    fn turns_bank_into_the_turn() {
+        // Flying along -Z and turning left towards -X
+        let points = (0..=8)
+            .map(|i| {
+                let angle = glm::half_pi::<f32>() * i as f32 / 8.0;
+                glm::vec3(angle.cos() - 1.0, 0.0, -angle.sin())
+            })
+            .collect();
+        let path = Path::new(CurveKind::CatmullRom, points, false).unwrap();
+        let heading = path.heading_at_distance(path.length() / 2.0, 30.0);
+        assert!(heading.roll > 0.1, "{}", heading.roll);
+        // Half way round a quarter turn it faces between -Z and -X
+        assert!(
+            (heading.yaw - glm::quarter_pi::<f32>()).abs() < 0.01,
+            "{}",
+            heading.yaw
+        );
+    }
+
+    #[test]
+    fn curves_pass_their_control_points() {
+        let points = uneven_points();
+
+        let catmull_rom = Path::new(CurveKind::CatmullRom, points.clone(), false).unwrap();
+        for (i, point) in points.iter().enumerate() {
+            assert!(close(&catmull_rom.point(i as f32), point, 1e-5));
+        }
+
+        // Bezier curves pass through every third point, and only come near the ones between
+        let bezier_points = points[..4].to_vec();
+        let bezier = Path::new(CurveKind::Bezier, bezier_points.clone(), false).unwrap();
+        assert_eq!(bezier.segments(), 1);
+        assert!(close(&bezier.point(0.0), &bezier_points[0], 1e-6));
+        assert!(close(&bezier.point(1.0), &bezier_points[3], 1e-5));
+        // Leaving along the line to the next point
+        let tangent = glm::normalize(&bezier.derivative(0.0));
+        let towards = glm::normalize(&(bezier_points[1] - bezier_points[0]));
+        assert!(close(&tangent, &towards, 1e-5));
+        let closed = Path::new(CurveKind::Bezier, points.clone(), true).unwrap();
+        assert!(close(&closed.point(1.0), &points[3], 1e-5));
+        assert!(close(&closed.point(2.0), &points[0], 1e-5));
+
+        // B-splines only pass near the points, at a sixth of the way to each neighbour
+        let bspline = Path::new(CurveKind::BSpline, points.clone(), false).unwrap();
+        for segment in 0..=bspline.segments() {
+            let near = (points[segment] + 4.0 * points[segment + 1] + points[segment + 2]) / 6.0;
+            assert!(close(&bspline.point(segment as f32), &near, 1e-5));
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::ptr;
+
+use crate::shader::{Shader, ShaderBuilder};
+
+// Offscreen target for the color-ID picking pass. Every drawable node is rendered with its own
+// unsigned integer ID into an R32UI texture, and the pixel under the cursor is read back.
+// An ID of 0 means that nothing was drawn there.
+pub struct PickingBuffer {
+    shader: Shader,
+    framebuffer: u32,
+    id_texture: u32,
+    depth_buffer: u32,
+    width: i32,
+    height: i32,
+}
+
+impl PickingBuffer {
+    pub unsafe fn new(width: u32, height: u32) -> Self {
+        let shader = ShaderBuilder::new()
+            .attach_file("./shaders/picking.frag")
+            .attach_file("./shaders/picking.vert")
+            .link();
+
+        let mut framebuffer = 0;
+        gl::GenFramebuffers(1, &mut framebuffer);
+        let mut id_texture = 0;
+        gl::GenTextures(1, &mut id_texture);
+        let mut depth_buffer = 0;
+        gl::GenRenderbuffers(1, &mut depth_buffer);
+
+        let mut picking_buffer = PickingBuffer {
+            shader,
+            framebuffer,
+            id_texture,
+            depth_buffer,
+            width: width as i32,
+            height: height as i32,
+        };
+        picking_buffer.allocate();
+        picking_buffer
+    }
+
+    // Has to be called whenever the window is resized, so the IDs line up with the cursor
+    pub unsafe fn resize(&mut self, width: u32, height: u32) {
+        self.width = width as i32;
+        self.height = height as i32;
+        self.allocate();
+    }
+
+    unsafe fn allocate(&mut self) {
+        gl::BindTexture(gl::TEXTURE_2D, self.id_texture);
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            gl::R32UI as i32,
+            self.width,
+            self.height,
+            0,
+            gl::RED_INTEGER,
+            gl::UNSIGNED_INT,
+            ptr::null(),
+        );
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+
+        gl::BindRenderbuffer(gl::RENDERBUFFER, self.depth_buffer);
+        gl::RenderbufferStorage(
+            gl::RENDERBUFFER,
+            gl::DEPTH_COMPONENT24,
+            self.width,
+            self.height,
+        );
+        gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+        gl::FramebufferTexture2D(
+            gl::FRAMEBUFFER,
+            gl::COLOR_ATTACHMENT0,
+            gl::TEXTURE_2D,
+            self.id_texture,
+            0,
+        );
+        gl::FramebufferRenderbuffer(
+            gl::FRAMEBUFFER,
+            gl::DEPTH_ATTACHMENT,
+            gl::RENDERBUFFER,
+            self.depth_buffer,
+        );
+        if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+            panic!("Picking framebuffer is incomplete!");
+        }
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+    }
+
+    // Bind the ID framebuffer and the picking shader, and clear both IDs and depth.
+    // Node IDs are written to the `uint` uniform at location 1.
+    pub unsafe fn begin(&self) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+        gl::Viewport(0, 0, self.width, self.height);
+        // Blending is undefined for integer color attachments
+        gl::Disable(gl::BLEND);
+        let no_id = [0_u32; 4];
+        gl::ClearBufferuiv(gl::COLOR, 0, no_id.as_ptr());
+        gl::Clear(gl::DEPTH_BUFFER_BIT);
+        self.shader.activate();
+    }
+
+    // Read back the ID under a cursor position given in window coordinates (origin top left)
+    pub unsafe fn read_id(&self, cursor_x: u32, cursor_y: u32) -> u32 {
+        let x = i32::min(cursor_x as i32, self.width - 1);
+        let y = self.height - 1 - i32::min(cursor_y as i32, self.height - 1);
+        let mut id = 0_u32;
+        gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+        gl::ReadPixels(
+            x,
+            y,
+            1,
+            1,
+            gl::RED_INTEGER,
+            gl::UNSIGNED_INT,
+            &mut id as *mut u32 as *mut std::ffi::c_void,
+        );
+        id
+    }
+
+    // Go back to drawing to the window. Remember to activate your own shader again afterwards.
+    pub unsafe fn end(&self) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl::Enable(gl::BLEND);
+    }
+}
@@ -0,0 +1,353 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+use glutin::event::{MouseButton, VirtualKeyCode};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::input::Bindings;
+
+// Recording what the user did, and doing it all over again.
+//
+// Every frame the render thread gathers the input the event loop forwarded to it into an
+// `InputFrame`, along with the time on the clock. A recording is those frames written to a
+// file, one line of RON each, as they happen, so a recording survives a crash. Replaying gives
+// the render thread the same frames, clock readings included, so the fixed timestep takes the
+// same steps with the same input and everything moves exactly like it did the first time.
+//
+// The first line holds the settings that change how things move, and a recording only replays
+// with the same ones. Keys are recorded rather than the actions they triggered, so the key
+// bindings are among them, and so is a hash of the scene file, which can change under the same
+// name.
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    pub scene: String,
+    pub scene_hash: u64,
+    pub helicopters: Option<usize>,
+    pub rotor_speed: f32,
+    pub mouse_sensitivity: f32,
+    pub invert_y: bool,
+    pub mouse_smoothing: f32,
+    pub bindings: Bindings,
+}
+
+impl Settings {
+    pub fn new(config: &Config, bindings: &Bindings) -> Result<Settings, String> {
+        let scene = std::fs::read(&config.scene)
+            .map_err(|e| format!("Failed to read scene file {}: {}", config.scene, e))?;
+        Ok(Settings {
+            scene: config.scene.clone(),
+            scene_hash: hash(&scene),
+            helicopters: config.helicopters,
+            rotor_speed: config.rotor_speed,
+            mouse_sensitivity: config.mouse_sensitivity,
+            invert_y: config.invert_y,
+            mouse_smoothing: config.mouse_smoothing,
+            bindings: bindings.clone(),
+        })
+    }
+
+    // The names of the settings that differ between the two
+    fn differences(&self, other: &Settings) -> Vec<&'static str> {
+        [
+            ("scene", self.scene != other.scene),
+            ("scene_hash", self.scene_hash != other.scene_hash),
+            ("helicopters", self.helicopters != other.helicopters),
+            ("rotor_speed", self.rotor_speed != other.rotor_speed),
+            (
+                "mouse_sensitivity",
+                self.mouse_sensitivity != other.mouse_sensitivity,
+            ),
+            ("invert_y", self.invert_y != other.invert_y),
+            (
+                "mouse_smoothing",
+                self.mouse_smoothing != other.mouse_smoothing,
+            ),
+            ("bindings", self.bindings != other.bindings),
+        ]
+        .iter()
+        .filter(|(_, differs)| *differs)
+        .map(|(name, _)| *name)
+        .collect()
+    }
+}
+
+// 64-bit FNV-1a, which unlike the standard library's hasher gives the same hash in every build
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct InputFrame {
+    // When the frame started, in seconds on the simulation clock
+    pub time: f64,
+    pub keys: Vec<VirtualKeyCode>,
+    pub buttons: Vec<MouseButton>,
+    // Mouse buttons pressed since the last frame, even if they were let go again already
+    #[serde(default)]
+    pub clicks: Vec<MouseButton>,
+    // Mouse movement in pixels and scroll wheel steps since the last frame
+    pub mouse_delta: (f32, f32),
+    pub scroll: f32,
+    pub cursor: (u32, u32),
+    // Whether the cursor was grabbed for mouse look while the mouse moved
+    #[serde(default)]
+    pub cursor_grabbed: bool,
+    // The new window size, if it changed since the last frame
+    pub resize: Option<(u32, u32)>,
+    // Lines typed into the console since the last frame
+    pub console: Vec<String>,
+}
+
+pub struct Recorder {
+    path: String,
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &str, settings: &Settings) -> Result<Recorder, String> {
+        let file = File::create(path)
+            .map_err(|e| format!("Failed to create recording {}: {}", path, e))?;
+        let mut recorder = Recorder {
+            path: path.to_string(),
+            writer: BufWriter::new(file),
+        };
+        recorder.write(settings)?;
+        Ok(recorder)
+    }
+
+    pub fn record(&mut self, frame: &InputFrame) -> Result<(), String> {
+        self.write(frame)
+    }
+
+    fn write(&mut self, value: &impl Serialize) -> Result<(), String> {
+        let line =
+            ron::to_string(value).map_err(|e| format!("Failed to serialize recording: {}", e))?;
+        writeln!(self.writer, "{}", line)
+            .and_then(|()| self.writer.flush())
+            .map_err(|e| format!("Failed to write recording {}: {}", self.path, e))
+    }
+}
+
+pub struct Replay {
+    frames: Vec<InputFrame>,
+    next: usize,
+}
+
+impl Replay {
+    // Load a recording, as long as it was made with the same settings
+    pub fn load(path: &str, settings: &Settings) -> Result<Replay, String> {
+        let file =
+            File::open(path).map_err(|e| format!("Failed to read recording {}: {}", path, e))?;
+        let mut lines = BufReader::new(file)
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+            .map(|(i, line)| {
+                line.map(|line| (i, line))
+                    .map_err(|e| format!("Failed to read recording {}: {}", path, e))
+            });
+
+        let (i, line) = lines
+            .next()
+            .unwrap_or_else(|| Err(format!("Recording {} is empty", path)))?;
+        let recorded: Settings = ron::from_str(&line)
+            .map_err(|e| format!("Failed to parse line {} of {}: {}", i + 1, path, e))?;
+        let differences = recorded.differences(settings);
+        if !differences.is_empty() {
+            return Err(format!(
+                "Recording {} was made with different settings: {}",
+                path,
+                differences.join(", ")
+            ));
+        }
+
+        let frames = lines
+            .map(|line| {
+                let (i, line) = line?;
+                ron::from_str(&line)
+                    .map_err(|e| format!("Failed to parse line {} of {}: {}", i + 1, path, e))
+            })
+            .collect::<Result<Vec<InputFrame>, String>>()?;
+        Ok(Replay { frames, next: 0 })
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    // The next frame to play, or None at the end of the recording
+    pub fn next_frame(&mut self) -> Option<InputFrame> {
+        let frame = self.frames.get(self.next).cloned();
+        self.next += frame.is_some() as usize;
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{Camera, MouseLook, Pose};
+    use crate::collision::TerrainCollider;
+    use crate::input::{Actions, Binding};
+    use crate::mesh::{BoundingBox, Mesh};
+    use crate::scene_file::{self, NodeDescription};
+    use crate::scene_graph::{NodeId, SceneGraph};
+    use crate::toolbox::FlightState;
+    use crate::world::World;
+
+    // Some seconds of flying the camera and the helicopter, at an uneven frame rate
+    fn frames() -> Vec<InputFrame> {
+        let mut time = 0.0;
+        (0..240)
+            .map(|i| {
+                time += 1.0 / 60.0 + (i % 7) as f64 * 0.002;
+                let held = |keys: &[(VirtualKeyCode, std::ops::Range<usize>)]| {
+                    keys.iter()
+                        .filter(|(_, frames)| frames.contains(&i))
+                        .map(|&(key, _)| key)
+                        .collect()
+                };
+                InputFrame {
+                    time,
+                    keys: held(&[
+                        (VirtualKeyCode::W, 0..60),
+                        (VirtualKeyCode::R, 20..120),
+                        (VirtualKeyCode::I, 80..150),
+                        (VirtualKeyCode::U, 130..170),
+                    ]),
+                    mouse_delta: if (100..200).contains(&i) {
+                        (3.0 + (i % 5) as f32, -1.5)
+                    } else {
+                        (0.0, 0.0)
+                    },
+                    // The last of the mouse movement is with the cursor let go
+                    cursor_grabbed: (90..180).contains(&i),
+                    ..InputFrame::default()
+                }
+            })
+            .collect()
+    }
+
+    // The scene the viewer starts with, with a box the size of a helicopter in place of every
+    // mesh, so nothing is loaded from disk, and flat ground to land on
+    fn load_world(scene: &mut SceneGraph, settings: &Settings) -> (NodeId, World) {
+        fn strip(node: &mut NodeDescription) {
+            node.mesh = None;
+            node.children.iter_mut().for_each(strip);
+        }
+        let mut description = scene_file::load(&settings.scene).unwrap();
+        strip(&mut description.root);
+        description.prefabs.values_mut().for_each(strip);
+        let root = description
+            .instantiate(scene, &mut |_, _| unreachable!())
+            .unwrap();
+        for node in scene.query(root, "*") {
+            scene[node].bounds = Some(BoundingBox {
+                min: glm::vec3(-0.05, 0.0, -0.05),
+                max: glm::vec3(0.05, 0.05, 0.05),
+            });
+        }
+        let ground = TerrainCollider::new(&Mesh::plane(10.0, [1.0; 4]), &scene.world_matrix(root));
+        let clips = description.load_clips().unwrap();
+        let world = World::new(scene, root, ground, clips, settings.rotor_speed).unwrap();
+        (root, world)
+    }
+
+    // Run the frames through the world the way the render loop does, and see where the camera
+    // and the helicopters and their rotors end up
+    fn simulate(
+        settings: &Settings,
+        frames: impl Iterator<Item = InputFrame>,
+    ) -> (Pose, FlightState, Vec<(glm::Vec3, glm::Quat)>) {
+        let mut scene = SceneGraph::new();
+        let (root, mut world) = load_world(&mut scene, settings);
+        let mut actions = Actions::new(settings.bindings.clone());
+        let mut camera = Camera::new(glm::vec3(0.0, 0.0, 3.0), 1.0);
+        let mut mouse_look = MouseLook::new(
+            settings.mouse_sensitivity,
+            settings.invert_y,
+            settings.mouse_smoothing,
+        );
+        for frame in frames {
+            actions.update(
+                &frame.keys,
+                &frame.buttons,
+                &frame.clicks,
+                frame.mouse_delta,
+                frame.scroll,
+            );
+            let delta_time = world.update(&mut scene, &actions, frame.time);
+            camera.update_from_actions(&actions, &mut mouse_look, frame.cursor_grabbed, delta_time);
+            camera.update(&scene, delta_time);
+        }
+        let mut nodes = scene.query(root, "*");
+        nodes.extend(scene.query(root, "*/*_rotor"));
+        let nodes = nodes
+            .into_iter()
+            .map(|node| (scene[node].position(), scene[node].orientation()))
+            .collect();
+        (camera.pose(), world.flight_model.state, nodes)
+    }
+
+    #[test]
+    fn replay_is_bit_identical() {
+        let path = std::env::temp_dir().join(format!("gloom-rs-replay-{}.ron", std::process::id()));
+        let path = path.to_str().unwrap();
+        let settings = Settings::new(&Config::default(), &Bindings::default()).unwrap();
+        let mut recorder = Recorder::create(path, &settings).unwrap();
+        for frame in frames() {
+            recorder.record(&frame).unwrap();
+        }
+        drop(recorder);
+
+        let live = simulate(&settings, frames().into_iter());
+        let replay = || {
+            let mut replay = Replay::load(path, &settings).unwrap();
+            assert_eq!(replay.len(), 240);
+            simulate(&settings, std::iter::from_fn(move || replay.next_frame()))
+        };
+        let (first, second) = (replay(), replay());
+        let mut rebound = settings.clone();
+        rebound
+            .bindings
+            .bind("move_forward", vec![Binding::Key(VirtualKeyCode::Up)]);
+        let edited = Settings {
+            scene_hash: settings.scene_hash + 1,
+            rotor_speed: 2.0,
+            ..settings.clone()
+        };
+        let refused = [
+            Replay::load(path, &rebound).err(),
+            Replay::load(path, &edited).err(),
+        ];
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(
+            refused,
+            [
+                Some(format!(
+                    "Recording {} was made with different settings: bindings",
+                    path
+                )),
+                Some(format!(
+                    "Recording {} was made with different settings: scene_hash, rotor_speed",
+                    path
+                )),
+            ]
+        );
+
+        // The mouse look has to have turned the camera, and the helicopters have to have flown
+        // and spun their rotors, for this to say anything about them
+        let start = simulate(&settings, std::iter::empty());
+        assert_eq!(start.2.len(), 6 + 6 * 2);
+        assert_ne!(live.0.orientation, start.0.orientation);
+        for (i, (node, start)) in live.2.iter().zip(&start.2).enumerate() {
+            assert_ne!(node, start, "node {}", i);
+        }
+        assert_eq!(first, live);
+        assert_eq!(second, live);
+    }
+}
@@ -0,0 +1,290 @@
+extern crate nalgebra_glm as glm;
+
+// Helpers for building and taking apart quaternion orientations.
+//
+// Euler angles are always given as a vector of (x, y, z) angles in radians, so for a node that
+// means (pitch, yaw, roll). The order says how the three axis rotations are multiplied together,
+// reading left to right: `YXZ` is Ry * Rx * Rz, which yaws first, then pitches about the yawed
+// X axis and finally rolls about the resulting Z axis.
+#[allow(dead_code, clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EulerOrder {
+    XYZ,
+    XZY,
+    YXZ,
+    YZX,
+    ZXY,
+    ZYX,
+}
+
+// The order used by `SceneNode::set_rotation` and `SceneNode::rotation`
+pub const DEFAULT_EULER_ORDER: EulerOrder = EulerOrder::YXZ;
+
+pub fn quat_from_euler(angles: &glm::Vec3, order: EulerOrder) -> glm::Quat {
+    let x = glm::quat_angle_axis(angles.x, &glm::vec3(1_f32, 0_f32, 0_f32));
+    let y = glm::quat_angle_axis(angles.y, &glm::vec3(0_f32, 1_f32, 0_f32));
+    let z = glm::quat_angle_axis(angles.z, &glm::vec3(0_f32, 0_f32, 1_f32));
+    match order {
+        EulerOrder::XYZ => x * y * z,
+        EulerOrder::XZY => x * z * y,
+        EulerOrder::YXZ => y * x * z,
+        EulerOrder::YZX => y * z * x,
+        EulerOrder::ZXY => z * x * y,
+        EulerOrder::ZYX => z * y * x,
+    }
+}
+
+// The inverse of `quat_from_euler`. The middle angle is kept within [-pi/2, pi/2], and when it
+// is at either end (gimbal lock) the whole remaining rotation is put in the first angle.
+pub fn quat_to_euler(orientation: &glm::Quat, order: EulerOrder) -> glm::Vec3 {
+    let m = glm::quat_to_mat3(&glm::quat_normalize(orientation));
+    let locked = |sine: f32| sine.abs() > 0.999_999_9;
+    let asin = |sine: f32| sine.clamp(-1_f32, 1_f32).asin();
+
+    match order {
+        EulerOrder::XYZ => {
+            let y = asin(m[(0, 2)]);
+            if locked(m[(0, 2)]) {
+                glm::vec3(m[(2, 1)].atan2(m[(1, 1)]), y, 0_f32)
+            } else {
+                glm::vec3(
+                    (-m[(1, 2)]).atan2(m[(2, 2)]),
+                    y,
+                    (-m[(0, 1)]).atan2(m[(0, 0)]),
+                )
+            }
+        }
+        EulerOrder::XZY => {
+            let z = asin(-m[(0, 1)]);
+            if locked(m[(0, 1)]) {
+                glm::vec3((-m[(1, 2)]).atan2(m[(2, 2)]), 0_f32, z)
+            } else {
+                glm::vec3(m[(2, 1)].atan2(m[(1, 1)]), m[(0, 2)].atan2(m[(0, 0)]), z)
+            }
+        }
+        EulerOrder::YXZ => {
+            let x = asin(-m[(1, 2)]);
+            if locked(m[(1, 2)]) {
+                glm::vec3(x, (-m[(2, 0)]).atan2(m[(0, 0)]), 0_f32)
+            } else {
+                glm::vec3(x, m[(0, 2)].atan2(m[(2, 2)]), m[(1, 0)].atan2(m[(1, 1)]))
+            }
+        }
+        EulerOrder::YZX => {
+            let z = asin(m[(1, 0)]);
+            if locked(m[(1, 0)]) {
+                glm::vec3(0_f32, m[(0, 2)].atan2(m[(2, 2)]), z)
+            } else {
+                glm::vec3(
+                    (-m[(1, 2)]).atan2(m[(1, 1)]),
+                    (-m[(2, 0)]).atan2(m[(0, 0)]),
+                    z,
+                )
+            }
+        }
+        EulerOrder::ZXY => {
+            let x = asin(m[(2, 1)]);
+            if locked(m[(2, 1)]) {
+                glm::vec3(x, 0_f32, m[(1, 0)].atan2(m[(0, 0)]))
+            } else {
+                glm::vec3(
+                    x,
+                    (-m[(2, 0)]).atan2(m[(2, 2)]),
+                    (-m[(0, 1)]).atan2(m[(1, 1)]),
+                )
+            }
+        }
+        EulerOrder::ZYX => {
+            let y = asin(-m[(2, 0)]);
+            if locked(m[(2, 0)]) {
+                glm::vec3(0_f32, y, (-m[(0, 1)]).atan2(m[(1, 1)]))
+            } else {
+                glm::vec3(m[(2, 1)].atan2(m[(2, 2)]), y, m[(1, 0)].atan2(m[(0, 0)]))
+            }
+        }
+    }
+}
+
+// A rotation of `angle` radians counterclockwise around `axis`, which need not be normalized
+#[allow(dead_code)]
+pub fn quat_from_axis_angle(axis: &glm::Vec3, angle: f32) -> glm::Quat {
+    glm::quat_angle_axis(angle, axis)
+}
+
+// The orientation that points a node's nose (its local -Z axis, like the helicopter model and
+// OpenGL cameras) along `forward`, keeping its local Y axis as close to `up` as possible
+pub fn quat_look_rotation(forward: &glm::Vec3, up: &glm::Vec3) -> glm::Quat {
+    let back = -glm::normalize(forward);
+    // Pick some other up vector if we are asked to look straight along it
+    let up = if glm::length(&glm::cross(up, &back)) < 1e-6 {
+        if back.y.abs() < 0.9 {
+            glm::vec3(0_f32, 1_f32, 0_f32)
+        } else {
+            glm::vec3(1_f32, 0_f32, 0_f32)
+        }
+    } else {
+        *up
+    };
+    let right = glm::normalize(&glm::cross(&up, &back));
+    let up = glm::cross(&back, &right);
+
+    glm::mat3_to_quat(&glm::mat3(
+        right.x, up.x, back.x, //
+        right.y, up.y, back.y, //
+        right.z, up.z, back.z,
+    ))
+}
+
+// Spherical interpolation from `a` to `b`, taking the shortest way around. Unlike the glm
+// version this does not give up on opposite rotations.
+pub fn quat_slerp(a: &glm::Quat, b: &glm::Quat, t: f32) -> glm::Quat {
+    let (a, mut b) = (glm::quat_normalize(a), glm::quat_normalize(b));
+    let mut cos_angle = glm::quat_dot(&a, &b);
+    if cos_angle < 0.0 {
+        b = -b;
+        cos_angle = -cos_angle;
+    }
+    // Nearly the same rotation, where plain linear interpolation is good enough
+    if cos_angle > 0.9995 {
+        return glm::quat_normalize(&(a * (1.0 - t) + b * t));
+    }
+    let angle = cos_angle.acos();
+    let sin_angle = angle.sin();
+    a * (((1.0 - t) * angle).sin() / sin_angle) + b * ((t * angle).sin() / sin_angle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORDERS: [EulerOrder; 6] = [
+        EulerOrder::XYZ,
+        EulerOrder::XZY,
+        EulerOrder::YXZ,
+        EulerOrder::YZX,
+        EulerOrder::ZXY,
+        EulerOrder::ZYX,
+    ];
+
+    // Which of the angles is the middle one, kept within [-pi/2, pi/2]
+    fn middle(order: EulerOrder) -> usize {
+        match order {
+            EulerOrder::YXZ | EulerOrder::ZXY => 0,
+            EulerOrder::XYZ | EulerOrder::ZYX => 1,
+            EulerOrder::XZY | EulerOrder::YZX => 2,
+        }
+    }
+
+    // How far apart two orientations are, as the largest difference between their matrices
+    fn difference(a: &glm::Quat, b: &glm::Quat) -> f32 {
+        (glm::quat_to_mat3(a) - glm::quat_to_mat3(b)).abs().max()
+    }
+
+    #[test]
+    fn euler_round_trip() {
+        for order in ORDERS {
+            for angles in [
+                glm::vec3(0.4, -1.1, 0.7),
+                glm::vec3(-2.5, 0.3, 3.0),
+                glm::vec3(0.0, 0.0, 0.0),
+            ] {
+                // Only the middle angle has to stay within a quarter turn
+                let mut angles: glm::Vec3 = angles;
+                angles[middle(order)] = angles[middle(order)].clamp(-1.5, 1.5);
+                let orientation = quat_from_euler(&angles, order);
+                let back = quat_to_euler(&orientation, order);
+                assert!(
+                    (back - angles).abs().max() < 1e-4,
+                    "{:?}: {} came back as {}",
+                    order,
+                    angles,
+                    back
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn euler_round_trip_at_gimbal_lock() {
+        for order in ORDERS {
+            for middle_angle in [
+                glm::half_pi::<f32>(),
+                -glm::half_pi::<f32>(),
+                glm::half_pi::<f32>() - 1e-4,
+                -glm::half_pi::<f32>() + 1e-3,
+            ] {
+                let mut angles = glm::vec3(0.6, -0.9, 1.3);
+                angles[middle(order)] = middle_angle;
+                let orientation = quat_from_euler(&angles, order);
+                let back = quat_to_euler(&orientation, order);
+                // The first and last angles turn about the same axis here, so only the
+                // orientation they make together has to come back
+                let again = quat_from_euler(&back, order);
+                assert!(
+                    difference(&orientation, &again) < 2e-3,
+                    "{:?}: {} came back as {}",
+                    order,
+                    angles,
+                    back
+                );
+                assert!(back.iter().all(|angle| angle.is_finite()));
+            }
+        }
+    }
+
+    #[test]
+    fn euler_orders_multiply_left_to_right() {
+        let angles = glm::vec3(0.3, 0.5, 0.7);
+        let x = quat_from_axis_angle(&glm::vec3(1.0, 0.0, 0.0), 0.3);
+        let y = quat_from_axis_angle(&glm::vec3(0.0, 1.0, 0.0), 0.5);
+        let z = quat_from_axis_angle(&glm::vec3(0.0, 0.0, 1.0), 0.7);
+        let yxz = quat_from_euler(&angles, EulerOrder::YXZ);
+        assert!(difference(&yxz, &(y * x * z)) < 1e-6);
+        assert!(difference(&yxz, &(z * x * y)) > 0.1);
+    }
+
+    #[test]
+    fn look_rotation_points_the_nose() {
+        let up = glm::vec3(0.0, 1.0, 0.0);
+        for forward in [
+            glm::vec3(1.0, 0.0, 0.0),
+            glm::vec3(0.0, 0.0, -1.0),
+            glm::vec3(-2.0, 1.0, 3.0),
+            // Straight along `up`, where another up has to be picked
+            glm::vec3(0.0, 5.0, 0.0),
+            glm::vec3(0.0, -1.0, 0.0),
+        ] {
+            let orientation = quat_look_rotation(&forward, &up);
+            let nose = glm::quat_rotate_vec3(&orientation, &glm::vec3(0.0, 0.0, -1.0));
+            assert!(glm::distance(&nose, &glm::normalize(&forward)) < 1e-5);
+            assert!((glm::quat_length(&orientation) - 1.0).abs() < 1e-5);
+        }
+
+        // The node stays upright, with its own Y axis as close to up as it can be
+        let orientation = quat_look_rotation(&glm::vec3(1.0, -0.5, 0.0), &up);
+        let node_up = glm::quat_rotate_vec3(&orientation, &glm::vec3(0.0, 1.0, 0.0));
+        let node_right = glm::quat_rotate_vec3(&orientation, &glm::vec3(1.0, 0.0, 0.0));
+        assert!(node_up.y > 0.8);
+        assert!(node_right.y.abs() < 1e-5);
+    }
+
+    #[test]
+    fn slerp() {
+        let a = glm::quat_identity();
+        let b = quat_from_axis_angle(&glm::vec3(0.0, 1.0, 0.0), 2.0);
+        assert!(difference(&quat_slerp(&a, &b, 0.0), &a) < 1e-6);
+        assert!(difference(&quat_slerp(&a, &b, 1.0), &b) < 1e-6);
+        let quarter = quat_from_axis_angle(&glm::vec3(0.0, 1.0, 0.0), 0.5);
+        assert!(difference(&quat_slerp(&a, &b, 0.25), &quarter) < 1e-6);
+
+        // The same rotation with the other sign still goes the short way
+        let halfway = quat_from_axis_angle(&glm::vec3(0.0, 1.0, 0.0), 1.0);
+        assert!(difference(&quat_slerp(&a, &-b, 0.5), &halfway) < 1e-6);
+        // Opposite rotations and nearly equal ones give something sensible
+        let opposite = quat_from_axis_angle(&glm::vec3(0.0, 1.0, 0.0), glm::pi());
+        let middle = quat_slerp(&a, &opposite, 0.5);
+        assert!((glm::quat_length(&middle) - 1.0).abs() < 1e-5);
+        let close = quat_from_axis_angle(&glm::vec3(1.0, 0.0, 0.0), 1e-4);
+        assert!(difference(&quat_slerp(&a, &close, 0.5), &a) < 1e-4);
+    }
+}
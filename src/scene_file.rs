@@ -0,0 +1,331 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::animation::{self, Clip, ClipDescription};
+use crate::mesh::{self, BoundingBox, Mesh, MeshSource};
+use crate::rotation::{self, DEFAULT_EULER_ORDER};
+use crate::scene_graph::{NodeId, SceneGraph, SceneNode};
+
+// A scene as it is written in a RON file: a tree of named nodes with their transforms and the
+// meshes they draw. Positions and reference points are in the same units as `SceneNode` uses,
+// and rotations are (pitch, yaw, roll) Euler angles in radians, as for `set_rotation`.
+// Everything but the name can be left out, see `resources/scene.ron` for an example.
+// Animation clips can be written out in the file itself, or taken from glTF files.
+//
+// Subtrees used several times, like a helicopter, can be described once as a prefab and placed
+// by naming it in the `prefab` of a node. Each prefab is built once, and every node using it gets
+// a copy of that, drawing the same VAOs.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SceneDescription {
+    pub root: NodeDescription,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub prefabs: BTreeMap<String, NodeDescription>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub animations: Vec<ClipDescription>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub animation_files: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NodeDescription {
+    pub name: String,
+    // Make this node a copy of a prefab, with this node's name, transform and children added.
+    // The prefab's mesh is drawn instead of `mesh`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefab: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mesh: Option<MeshSource>,
+    #[serde(default)]
+    pub position: [f32; 3],
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    #[serde(default = "unit_scale")]
+    pub scale: [f32; 3],
+    #[serde(default)]
+    pub reference_point: [f32; 3],
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<NodeDescription>,
+}
+
+fn unit_scale() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+pub fn load(path: &str) -> Result<SceneDescription, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read scene file {}: {}", path, e))?;
+    ron::from_str(&text).map_err(|e| format!("Failed to parse scene file {}: {}", path, e))
+}
+
+pub fn save(path: &str, description: &SceneDescription) -> Result<(), String> {
+    let text = ron::ser::to_string_pretty(description, ron::ser::PrettyConfig::default())
+        .map_err(|e| format!("Failed to serialize scene: {}", e))?;
+    std::fs::write(path, text).map_err(|e| format!("Failed to write scene file {}: {}", path, e))
+}
+
+impl SceneDescription {
+    // Build the described nodes in `scene` and return the root. `create_vao` is called once
+    // for every distinct mesh along with where it came from, and nodes drawing the same mesh
+    // share its VAO.
+    pub fn instantiate(
+        &self,
+        scene: &mut SceneGraph,
+        create_vao: &mut dyn FnMut(&MeshSource, &Mesh) -> u32,
+    ) -> Result<NodeId, String> {
+        let mut builder = Builder {
+            loader: MeshLoader {
+                files: HashMap::new(),
+                vaos: vec![],
+                create_vao,
+            },
+            prefabs: &self.prefabs,
+            templates: HashMap::new(),
+        };
+        let root = self.root.instantiate(scene, &mut builder);
+        // The prefabs were only built to be copied, and the copies still draw their VAOs
+        for template in builder.templates.into_values().flatten() {
+            scene.remove(template);
+        }
+        root
+    }
+
+    // Every clip in the file, followed by the ones in the glTF files it lists
+    pub fn load_clips(&self) -> Result<Vec<Clip>, String> {
+        let mut clips = self
+            .animations
+            .iter()
+            .map(|clip| clip.to_clip())
+            .collect::<Result<Vec<Clip>, String>>()?;
+        for path in &self.animation_files {
+            clips.extend(animation::load_gltf_clips(path)?);
+        }
+        Ok(clips)
+    }
+
+    // Describe the subtree below `root`, built from `loaded`, so it can be saved. Nodes are
+    // written in their rest pose rather than wherever they were animated to, and copies of
+    // prefabs refer to them again. The prefabs and animations are taken from `loaded`, since
+    // the scene graph has no record of them.
+    pub fn from_scene(
+        scene: &SceneGraph,
+        root: NodeId,
+        loaded: &SceneDescription,
+    ) -> SceneDescription {
+        SceneDescription {
+            root: NodeDescription::from_scene(scene, root, &loaded.prefabs),
+            prefabs: loaded.prefabs.clone(),
+            animations: loaded.animations.clone(),
+            animation_files: loaded.animation_files.clone(),
+        }
+    }
+}
+
+impl NodeDescription {
+    // The first node named `name` at or below this one
+    pub fn find(&self, name: &str) -> Option<&NodeDescription> {
+        if self.name == name {
+            return Some(self);
+        }
+        self.children.iter().find_map(|child| child.find(name))
+    }
+
+    fn instantiate(&self, scene: &mut SceneGraph, builder: &mut Builder) -> Result<NodeId, String> {
+        let node = match (&self.prefab, &self.mesh) {
+            (Some(prefab), _) => {
+                let template = builder.template(scene, prefab)?;
+                scene.clone_subtree(template)
+            }
+            (None, Some(source)) => {
+                let (vao_id, index_count, bounds) = builder.loader.load(source)?;
+                let mut node = SceneNode::from_vao(vao_id, index_count);
+                node.mesh_source = Some(source.clone());
+                node.bounds = bounds;
+                scene.add(node)
+            }
+            (None, None) => scene.add(SceneNode::new()),
+        };
+        let transformed = &mut scene[node];
+        transformed.name = self.name.clone();
+        transformed.set_position(glm::make_vec3(&self.position));
+        transformed.set_rotation(glm::make_vec3(&self.rotation));
+        transformed.set_scale(glm::make_vec3(&self.scale));
+        transformed.set_reference_point(glm::make_vec3(&self.reference_point));
+        transformed.set_rest_pose();
+        transformed.prefab = self.prefab.clone();
+
+        for child in &self.children {
+            let child = child.instantiate(scene, builder)?;
+            scene.add_child(node, child);
+        }
+        Ok(node)
+    }
+
+    fn from_scene(
+        scene: &SceneGraph,
+        id: NodeId,
+        prefabs: &BTreeMap<String, NodeDescription>,
+    ) -> NodeDescription {
+        let node = &scene[id];
+        let rest = node.rest_pose();
+        // A copy of a prefab starts with the prefab's children, which the prefab describes
+        let copied = node
+            .prefab
+            .as_ref()
+            .and_then(|prefab| prefabs.get(prefab))
+            .map_or(0, |prefab| prefab.children.len());
+        NodeDescription {
+            name: node.name.clone(),
+            prefab: node.prefab.clone(),
+            mesh: node.mesh_source.clone().filter(|_| node.prefab.is_none()),
+            position: rest.position.into(),
+            rotation: rotation::quat_to_euler(&rest.orientation, DEFAULT_EULER_ORDER).into(),
+            scale: rest.scale.into(),
+            reference_point: node.reference_point().into(),
+            children: node
+                .children()
+                .iter()
+                .skip(copied)
+                .map(|&child| NodeDescription::from_scene(scene, child, prefabs))
+                .collect(),
+        }
+    }
+}
+
+// Everything needed while building a scene from its description
+struct Builder<'a> {
+    loader: MeshLoader<'a>,
+    prefabs: &'a BTreeMap<String, NodeDescription>,
+    // The detached subtree built for every prefab used so far, or None while it is being built
+    templates: HashMap<String, Option<NodeId>>,
+}
+
+impl Builder<'_> {
+    // The subtree to copy for a prefab, built the first time it is asked for
+    fn template(&mut self, scene: &mut SceneGraph, name: &str) -> Result<NodeId, String> {
+        match self.templates.get(name) {
+            Some(Some(template)) => return Ok(*template),
+            Some(None) => return Err(format!("The prefab {} contains itself", name)),
+            None => {}
+        }
+        let prefabs = self.prefabs;
+        let prefab = prefabs
+            .get(name)
+            .ok_or_else(|| format!("The scene has no prefab named {}", name))?;
+        self.templates.insert(name.to_string(), None);
+        let template = prefab.instantiate(scene, self)?;
+        self.templates.insert(name.to_string(), Some(template));
+        Ok(template)
+    }
+}
+
+// Keeps every OBJ file and mesh that has been loaded, so each is only read and uploaded once
+struct MeshLoader<'a> {
+    files: HashMap<String, Vec<tobj::Model>>,
+    vaos: Vec<(MeshSource, u32, i32, Option<BoundingBox>)>,
+    create_vao: &'a mut dyn FnMut(&MeshSource, &Mesh) -> u32,
+}
+
+impl MeshLoader<'_> {
+    fn load(&mut self, source: &MeshSource) -> Result<(u32, i32, Option<BoundingBox>), String> {
+        if let Some((_, vao_id, index_count, bounds)) =
+            self.vaos.iter().find(|(s, _, _, _)| s == source)
+        {
+            return Ok((*vao_id, *index_count, *bounds));
+        }
+        if !self.files.contains_key(&source.path) {
+            let models = mesh::load_obj_models(&source.path)?;
+            self.files.insert(source.path.clone(), models);
+        }
+        let mesh = Mesh::from_source(&self.files[&source.path], source)?;
+        let vao_id = (self.create_vao)(source, &mesh);
+        let index_count = mesh.indices.len() as i32;
+        let bounds = mesh.bounding_box();
+        self.vaos
+            .push((source.clone(), vao_id, index_count, bounds));
+        Ok((vao_id, index_count, bounds))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A prefab used twice, once with a child of its own, and nothing to load from disk
+    const SCENE: &str = r#"(
+        root: (
+            name: "root",
+            children: [
+                (name: "first", prefab: Some("helicopter"), position: (1.0, 2.0, 3.0)),
+                (
+                    name: "second",
+                    prefab: Some("helicopter"),
+                    rotation: (0.1, 0.2, 0.3),
+                    scale: (2.0, 2.0, 2.0),
+                    children: [(name: "light", position: (0.0, 1.0, 0.0))],
+                ),
+            ],
+        ),
+        prefabs: {
+            "helicopter": (
+                name: "helicopter",
+                children: [
+                    (name: "main_rotor", reference_point: (0.5, 0.0, 0.0)),
+                    (name: "tail_rotor", rotation: (1.0, 0.0, 0.0)),
+                ],
+            ),
+        },
+    )"#;
+
+    fn assert_same(a: &NodeDescription, b: &NodeDescription) {
+        assert_eq!(a.name, b.name);
+        assert_eq!(a.prefab, b.prefab, "{}", a.name);
+        assert_eq!(a.mesh, b.mesh, "{}", a.name);
+        for (x, y) in [
+            (a.position, b.position),
+            (a.rotation, b.rotation),
+            (a.scale, b.scale),
+            (a.reference_point, b.reference_point),
+        ] {
+            let difference = glm::make_vec3(&x) - glm::make_vec3(&y);
+            assert!(
+                difference.abs().max() < 1e-5,
+                "{}: {:?} != {:?}",
+                a.name,
+                x,
+                y
+            );
+        }
+        assert_eq!(a.children.len(), b.children.len(), "{}", a.name);
+        for (a, b) in a.children.iter().zip(&b.children) {
+            assert_same(a, b);
+        }
+    }
+
+    #[test]
+    fn load_save_load() {
+        let loaded: SceneDescription = ron::from_str(SCENE).unwrap();
+        let mut scene = SceneGraph::new();
+        let root = loaded
+            .instantiate(&mut scene, &mut |_, _| unreachable!())
+            .unwrap();
+        // Flying and animating moves the nodes away from where the file put them
+        let second = scene.find(root, "second").unwrap();
+        scene[second].set_position(glm::vec3(10.0, 0.0, 0.0));
+        let rotor = scene.find(root, "first/main_rotor").unwrap();
+        scene[rotor].set_rotation(glm::vec3(0.0, 2.0, 0.0));
+
+        let path = std::env::temp_dir().join(format!("gloom-rs-scene-{}.ron", std::process::id()));
+        let path = path.to_str().unwrap();
+        save(path, &SceneDescription::from_scene(&scene, root, &loaded)).unwrap();
+        let saved = load(path);
+        std::fs::remove_file(path).unwrap();
+        let saved = saved.unwrap();
+
+        assert_same(&saved.root, &loaded.root);
+        assert_eq!(saved.prefabs.len(), 1);
+        assert_same(&saved.prefabs["helicopter"], &loaded.prefabs["helicopter"]);
+    }
+}
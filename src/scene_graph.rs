@@ -1,66 +1,221 @@
 extern crate nalgebra_glm as glm;
 
-use std::mem::ManuallyDrop;
-use std::pin::Pin;
-
-// Used to create an unholy abomination upon which you should not cast your gaze. This ended up
-// being a necessity due to wanting to keep the code written by students as "straight forward" as
-// possible. It is very very double plus ungood Rust, and intentionally leaks memory like a sieve.
-// But it works, and you're more than welcome to pretend it doesn't exist! In case you're curious
-// about how it works: It allocates memory on the heap (Box), promises to prevent it from being
-// moved or deallocated until dropped (Pin) and finally prevents the compiler from dropping it
-// automatically at all (ManuallyDrop).
-// ...
-// If that sounds like a janky solution, it's because it is!
-// Prettier, Rustier and better solutions were tried numerous times, but were all found wanting of
-// having what I arbitrarily decided to be the required level of "simplicity of use".
-pub type Node = ManuallyDrop<Pin<Box<SceneNode>>>;
+use std::cell::Cell;
+use std::ops::{Index, IndexMut};
+
+use crate::mesh::{BoundingBox, MeshSource};
+use crate::rotation::{self, DEFAULT_EULER_ORDER};
+
+// The scene graph owns every node in a single arena (a Vec of slots), and nodes refer to each
+// other through `NodeId` handles rather than pointers. A handle remembers the generation of the
+// slot it was handed out for, so a handle to a removed node can never be confused with whatever
+// node is later stored in the same slot. Using a stale handle with the square brackets panics,
+// while `get` and `get_mut` return `None`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    index: u32,
+    generation: u32,
+}
+
+// A node's transform as it was authored, before anything animated or moved it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RestPose {
+    pub position: glm::Vec3,
+    pub orientation: glm::Quat,
+    pub scale: glm::Vec3,
+}
 
+// Every node caches the matrix from its own space to its parent's (local) and to the world. The
+// transform fields are only reachable through setters, which mark the node dirty, and the
+// matrices are rebuilt the next time they are asked for. A node whose world matrix is rebuilt
+// marks its children dirty in turn, so changes trickle down the tree lazily.
+#[derive(Clone)]
 pub struct SceneNode {
-    pub position: glm::Vec3,        // Where I should be in relation to my parent
-    pub rotation: glm::Vec3,        // How I should be rotated, around the X, the Y and the Z axes
-    pub scale: glm::Vec3,           // How I should be scaled
-    pub reference_point: glm::Vec3, // The point I shall rotate and scale about
+    pub name: String, // What I am called, used to look me up by path
+
+    position: glm::Vec3,        // Where I should be in relation to my parent
+    orientation: glm::Quat,     // How I should be rotated
+    scale: glm::Vec3,           // How I should be scaled
+    reference_point: glm::Vec3, // The point I shall rotate and scale about
+
+    pub vao_id: u32,                     // What I should draw
+    pub index_count: i32,                // How much of it there is to draw
+    pub mesh_source: Option<MeshSource>, // Where what I draw was loaded from, if anywhere
+    pub bounds: Option<BoundingBox>,     // The box around what I draw, in my own space
+    pub prefab: Option<String>,          // The prefab I am a copy of, if any
+    rest_pose: Option<RestPose>,         // How I was placed when loaded, if I was
 
-    pub vao_id: u32,      // What I should draw
-    pub index_count: i32, // How much of it there is to draw
+    children: Vec<NodeId>,  // Those I command
+    parent: Option<NodeId>, // The one I answer to
 
-    pub children: Vec<*mut SceneNode>, // Those I command
+    local_matrix: Cell<glm::Mat4>, // My space to my parent's space
+    world_matrix: Cell<glm::Mat4>, // My space to world space
+    local_dirty: Cell<bool>,       // Whether local_matrix is stale
+    world_dirty: Cell<bool>,       // Whether world_matrix is stale, for me and all below me
 }
 
 impl SceneNode {
-    pub fn new() -> Node {
-        ManuallyDrop::new(Pin::new(Box::new(SceneNode {
+    #[allow(dead_code)]
+    pub fn new() -> SceneNode {
+        SceneNode {
+            name: String::new(),
             position: glm::zero(),
-            rotation: glm::zero(),
+            orientation: glm::quat_identity(),
             scale: glm::vec3(1.0, 1.0, 1.0),
             reference_point: glm::zero(),
             vao_id: 0,
             index_count: -1,
+            mesh_source: None,
+            bounds: None,
+            prefab: None,
+            rest_pose: None,
             children: vec![],
-        })))
+            parent: None,
+            local_matrix: Cell::new(glm::identity()),
+            world_matrix: Cell::new(glm::identity()),
+            local_dirty: Cell::new(true),
+            world_dirty: Cell::new(true),
+        }
     }
 
-    pub fn from_vao(vao_id: u32, index_count: i32) -> Node {
-        ManuallyDrop::new(Pin::new(Box::new(SceneNode {
-            position: glm::zero(),
-            rotation: glm::zero(),
-            scale: glm::vec3(1.0, 1.0, 1.0),
-            reference_point: glm::zero(),
+    pub fn from_vao(vao_id: u32, index_count: i32) -> SceneNode {
+        SceneNode {
             vao_id,
             index_count,
-            children: vec![],
-        })))
+            ..SceneNode::new()
+        }
+    }
+
+    pub fn with_name(mut self, name: &str) -> SceneNode {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn position(&self) -> glm::Vec3 {
+        self.position
+    }
+
+    #[allow(dead_code)]
+    pub fn orientation(&self) -> glm::Quat {
+        self.orientation
+    }
+
+    // My orientation as (pitch, yaw, roll) Euler angles, see `rotation::DEFAULT_EULER_ORDER`
+    pub fn rotation(&self) -> glm::Vec3 {
+        rotation::quat_to_euler(&self.orientation, DEFAULT_EULER_ORDER)
+    }
+
+    pub fn scale(&self) -> glm::Vec3 {
+        self.scale
+    }
+
+    pub fn reference_point(&self) -> glm::Vec3 {
+        self.reference_point
+    }
+
+    pub fn set_position(&mut self, position: glm::Vec3) {
+        self.position = position;
+        self.mark_dirty();
+    }
+
+    pub fn set_orientation(&mut self, orientation: glm::Quat) {
+        self.orientation = glm::quat_normalize(&orientation);
+        self.mark_dirty();
+    }
+
+    // Set my orientation from (pitch, yaw, roll) Euler angles: yaw around Y first, then pitch
+    // around the yawed X axis and finally roll around the resulting Z axis. Use
+    // `set_orientation` together with `rotation::quat_from_euler` for any other order.
+    pub fn set_rotation(&mut self, rotation: glm::Vec3) {
+        self.set_orientation(rotation::quat_from_euler(&rotation, DEFAULT_EULER_ORDER));
+    }
+
+    pub fn set_scale(&mut self, scale: glm::Vec3) {
+        self.scale = scale;
+        self.mark_dirty();
+    }
+
+    pub fn set_reference_point(&mut self, reference_point: glm::Vec3) {
+        self.reference_point = reference_point;
+        self.mark_dirty();
+    }
+
+    // Remember my current transform as the one I rest in, before anything moves me
+    pub fn set_rest_pose(&mut self) {
+        self.rest_pose = Some(RestPose {
+            position: self.position,
+            orientation: self.orientation,
+            scale: self.scale,
+        });
+    }
+
+    // The transform I rest in, or my current one if none was set
+    pub fn rest_pose(&self) -> RestPose {
+        self.rest_pose.unwrap_or(RestPose {
+            position: self.position,
+            orientation: self.orientation,
+            scale: self.scale,
+        })
+    }
+
+    fn mark_dirty(&self) {
+        self.local_dirty.set(true);
+        self.world_dirty.set(true);
+    }
+
+    // The transformation from my own space into my parent's space
+    pub fn local_matrix(&self) -> glm::Mat4 {
+        if self.local_dirty.get() {
+            let translate_origin: glm::Mat4 = glm::translation(&-self.reference_point);
+            let translate_reference: glm::Mat4 = glm::translation(&self.reference_point);
+
+            let translation = glm::translation(&self.position);
+
+            self.local_matrix.set(
+                translation
+                    * translate_reference
+                    * glm::quat_to_mat4(&self.orientation)
+                    * glm::scaling(&self.scale)
+                    * translate_origin,
+            );
+            self.local_dirty.set(false);
+        }
+        self.local_matrix.get()
+    }
+
+    // Set position, orientation and scale so that `local_matrix` becomes `matrix`, keeping the
+    // reference point. Shearing can not be represented and is lost.
+    #[allow(dead_code)]
+    pub fn set_local_matrix(&mut self, matrix: &glm::Mat4) {
+        let linear = glm::mat4_to_mat3(matrix);
+        let scale = glm::vec3(
+            glm::length(&linear.column(0).into_owned()),
+            glm::length(&linear.column(1).into_owned()),
+            glm::length(&linear.column(2).into_owned()),
+        );
+        let rotation = linear * glm::diagonal3x3(&scale.map(|s| 1_f32 / s));
+
+        self.orientation = glm::quat_normalize(&glm::mat3_to_quat(&rotation));
+        self.scale = scale;
+
+        // The translation column is position + reference_point - linear * reference_point
+        let translation = glm::vec3(matrix[(0, 3)], matrix[(1, 3)], matrix[(2, 3)]);
+        self.position = translation - self.reference_point + linear * self.reference_point;
+        self.mark_dirty();
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
     }
 
-    pub fn add_child(&mut self, child: &SceneNode) {
-        self.children
-            .push(child as *const SceneNode as *mut SceneNode)
+    #[allow(dead_code)]
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
     }
 
     #[allow(dead_code)]
-    pub fn get_child(&mut self, index: usize) -> &mut SceneNode {
-        unsafe { &mut (*self.children[index]) }
+    pub fn get_child(&self, index: usize) -> NodeId {
+        self.children[index]
     }
 
     #[allow(dead_code)]
@@ -72,6 +227,7 @@ impl SceneNode {
     pub fn print(&self) {
         println!(
             "SceneNode {{
+    Name:      {}
     VAO:       {}
     Indices:   {}
     Children:  {}
@@ -79,15 +235,16 @@ impl SceneNode {
     Rotation:  [{:.2}, {:.2}, {:.2}]
     Reference: [{:.2}, {:.2}, {:.2}]
 }}",
+            self.name,
             self.vao_id,
             self.index_count,
             self.children.len(),
             self.position.x,
             self.position.y,
             self.position.z,
-            self.rotation.x,
-            self.rotation.y,
-            self.rotation.z,
+            self.rotation().x,
+            self.rotation().y,
+            self.rotation().z,
             self.reference_point.x,
             self.reference_point.y,
             self.reference_point.z,
@@ -95,16 +252,560 @@ impl SceneNode {
     }
 }
 
-// You can also use square brackets to access the children of a SceneNode
-use std::ops::{Index, IndexMut};
-impl Index<usize> for SceneNode {
+struct Slot {
+    generation: u32,
+    node: Option<SceneNode>,
+}
+
+#[derive(Default)]
+pub struct SceneGraph {
+    slots: Vec<Slot>,
+    free_slots: Vec<u32>, // Indices of empty slots, reused before the arena grows
+}
+
+impl SceneGraph {
+    pub fn new() -> SceneGraph {
+        SceneGraph::default()
+    }
+
+    // Move a node into the graph. It has no parent until it is given to `add_child`, and any
+    // child links it carries (from being cloned out of the graph) are dropped.
+    pub fn add(&mut self, mut node: SceneNode) -> NodeId {
+        node.children.clear();
+        node.parent = None;
+        node.world_dirty.set(true);
+        match self.free_slots.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.node = Some(node);
+                NodeId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    node: Some(node),
+                });
+                NodeId {
+                    index: self.slots.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    // Make `child` the last child of `parent`, detaching it from its previous parent if needed
+    pub fn add_child(&mut self, parent: NodeId, child: NodeId) {
+        assert!(
+            !self.is_ancestor_or_self(child, parent),
+            "A node can not become a child of its own subtree"
+        );
+        self.detach(child);
+        self[parent].children.push(child);
+        self[child].parent = Some(parent);
+        self[child].world_dirty.set(true);
+    }
+
+    // Remove a node from its parent's children, making it a root
+    pub fn detach(&mut self, node: NodeId) {
+        if let Some(parent) = self[node].parent.take() {
+            self[parent].children.retain(|&c| c != node);
+            self[node].world_dirty.set(true);
+        }
+    }
+
+    // Move a node (and its subtree) under a new parent. With `keep_world_transform` the node is
+    // given a new position, rotation and scale so it stays where it is in the world, otherwise
+    // its transform is kept relative to the new parent.
+    #[allow(dead_code)]
+    pub fn reparent(&mut self, node: NodeId, new_parent: NodeId, keep_world_transform: bool) {
+        let world_matrix = self.world_matrix(node);
+        self.add_child(new_parent, node);
+        if keep_world_transform {
+            let parent_inverse = glm::inverse(&self.world_matrix(new_parent));
+            self[node].set_local_matrix(&(parent_inverse * world_matrix));
+        }
+    }
+
+    // Remove a node and its whole subtree from the graph. Handles to any of them become stale.
+    // Returns the VAOs that were drawn by the removed nodes and by no node left in the graph, so
+    // the caller can free them on the GPU.
+    pub fn remove(&mut self, node: NodeId) -> Vec<u32> {
+        self.detach(node);
+        let mut released_vaos = vec![];
+        let mut stack = vec![node];
+        while let Some(id) = stack.pop() {
+            let slot = &mut self.slots[id.index as usize];
+            let removed = slot.node.take().expect("Node was already removed");
+            slot.generation = slot.generation.wrapping_add(1);
+            self.free_slots.push(id.index);
+            if removed.vao_id != 0 && !released_vaos.contains(&removed.vao_id) {
+                released_vaos.push(removed.vao_id);
+            }
+            stack.extend(removed.children);
+        }
+        released_vaos.retain(|&vao| self.nodes().all(|(_, n)| n.vao_id != vao));
+        released_vaos
+    }
+
+    // Make a deep copy of a node and its subtree. The copies draw the same VAOs as the
+    // originals, and the copied root has no parent.
+    pub fn clone_subtree(&mut self, node: NodeId) -> NodeId {
+        let copy = self.add(self[node].clone());
+        for child in self[node].children.clone() {
+            let child_copy = self.clone_subtree(child);
+            self.add_child(copy, child_copy);
+        }
+        copy
+    }
+
+    // Find the first direct child of `parent` with the given name
+    pub fn find_child(&self, parent: NodeId, name: &str) -> Option<NodeId> {
+        self[parent]
+            .children
+            .iter()
+            .copied()
+            .find(|&child| self[child].name == name)
+    }
+
+    // Follow a slash separated path of names down from `root`, e.g. "helicopter/main_rotor".
+    // Where several children share a name, the first one is followed.
+    pub fn find(&self, root: NodeId, path: &str) -> Option<NodeId> {
+        path.split('/')
+            .filter(|segment| !segment.is_empty())
+            .try_fold(root, |node, name| self.find_child(node, name))
+    }
+
+    // Every node below `root` matching a slash separated glob pattern, in depth first order.
+    // Within a segment `*` matches any run of characters and `?` any single character, while a
+    // `**` segment matches any number of levels, so "**/main_rotor" finds every main rotor.
+    pub fn query(&self, root: NodeId, pattern: &str) -> Vec<NodeId> {
+        let segments: Vec<&str> = pattern
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect();
+        let mut matches = vec![];
+        self.query_from(root, &segments, &mut matches);
+
+        let mut unique = vec![];
+        for node in matches {
+            if !unique.contains(&node) {
+                unique.push(node);
+            }
+        }
+        unique
+    }
+
+    fn query_from(&self, node: NodeId, segments: &[&str], matches: &mut Vec<NodeId>) {
+        match segments.split_first() {
+            None => matches.push(node),
+            Some((&"**", rest)) => {
+                self.query_from(node, rest, matches);
+                for &child in &self[node].children {
+                    self.query_from(child, segments, matches);
+                }
+            }
+            Some((segment, rest)) => {
+                for &child in &self[node].children {
+                    if glob_match(segment, &self[child].name) {
+                        self.query_from(child, rest, matches);
+                    }
+                }
+            }
+        }
+    }
+
+    // The transformation from a node's own space into world space. Only the nodes on the path
+    // from the topmost dirty ancestor down to this node are recomputed.
+    pub fn world_matrix(&self, node: NodeId) -> glm::Mat4 {
+        let mut path = vec![];
+        let mut topmost_dirty = None;
+        let mut current = Some(node);
+        while let Some(id) = current {
+            path.push(id);
+            if self[id].world_dirty.get() {
+                topmost_dirty = Some(path.len() - 1);
+            }
+            current = self[id].parent;
+        }
+
+        if let Some(topmost_dirty) = topmost_dirty {
+            for &id in path[..=topmost_dirty].iter().rev() {
+                let node = &self[id];
+                let parent_matrix = node
+                    .parent
+                    .map_or_else(glm::identity, |parent| self[parent].world_matrix.get());
+                node.world_matrix.set(parent_matrix * node.local_matrix());
+                node.world_dirty.set(false);
+                for &child in &node.children {
+                    self[child].world_dirty.set(true);
+                }
+            }
+        }
+        self[node].world_matrix.get()
+    }
+
+    // Where a node's origin currently is in world space
+    pub fn world_position(&self, node: NodeId) -> glm::Vec3 {
+        let matrix = self.world_matrix(node);
+        glm::vec3(matrix[(0, 3)], matrix[(1, 3)], matrix[(2, 3)])
+    }
+
+    // Iterate over every node in the graph, in no particular order
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeId, &SceneNode)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.node.as_ref().map(|node| {
+                (
+                    NodeId {
+                        index: index as u32,
+                        generation: slot.generation,
+                    },
+                    node,
+                )
+            })
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn contains(&self, node: NodeId) -> bool {
+        self.get(node).is_some()
+    }
+
+    pub fn get(&self, node: NodeId) -> Option<&SceneNode> {
+        self.slots
+            .get(node.index as usize)
+            .filter(|slot| slot.generation == node.generation)
+            .and_then(|slot| slot.node.as_ref())
+    }
+
+    pub fn get_mut(&mut self, node: NodeId) -> Option<&mut SceneNode> {
+        self.slots
+            .get_mut(node.index as usize)
+            .filter(|slot| slot.generation == node.generation)
+            .and_then(|slot| slot.node.as_mut())
+    }
+
+    fn is_ancestor_or_self(&self, ancestor: NodeId, node: NodeId) -> bool {
+        let mut current = Some(node);
+        while let Some(id) = current {
+            if id == ancestor {
+                return true;
+            }
+            current = self[id].parent;
+        }
+        false
+    }
+}
+
+// Match a single path segment against a pattern where `*` is any run of characters and `?` is
+// any single character
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // Classic backtracking matcher: remember the last star, and let it swallow one more
+    // character whenever the rest of the pattern fails to match
+    let (mut p, mut n) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            last_star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = last_star {
+            p = star_p + 1;
+            n = star_n + 1;
+            last_star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Square brackets give direct access to a node, and `graph[(parent, i)]` to its i-th child
+impl Index<NodeId> for SceneGraph {
     type Output = SceneNode;
-    fn index(&self, index: usize) -> &SceneNode {
-        unsafe { &*(self.children[index] as *const SceneNode) }
+    fn index(&self, node: NodeId) -> &SceneNode {
+        self.get(node).expect("Stale or invalid NodeId")
+    }
+}
+impl IndexMut<NodeId> for SceneGraph {
+    fn index_mut(&mut self, node: NodeId) -> &mut SceneNode {
+        self.get_mut(node).expect("Stale or invalid NodeId")
     }
 }
-impl IndexMut<usize> for SceneNode {
-    fn index_mut(&mut self, index: usize) -> &mut SceneNode {
-        unsafe { &mut (*self.children[index]) }
+impl Index<(NodeId, usize)> for SceneGraph {
+    type Output = SceneNode;
+    fn index(&self, (parent, index): (NodeId, usize)) -> &SceneNode {
+        &self[self[parent].children[index]]
+    }
+}
+impl IndexMut<(NodeId, usize)> for SceneGraph {
+    fn index_mut(&mut self, (parent, index): (NodeId, usize)) -> &mut SceneNode {
+        let child = self[parent].children[index];
+        &mut self[child]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn named(graph: &mut SceneGraph, name: &str) -> NodeId {
+        graph.add(SceneNode::new().with_name(name))
+    }
+
+    #[test]
+    fn stale_ids_after_reuse() {
+        let mut graph = SceneGraph::new();
+        let old = named(&mut graph, "old");
+        graph.remove(old);
+        assert!(!graph.contains(old));
+
+        // The new node gets the old slot, but not the old handle
+        let new = named(&mut graph, "new");
+        assert_eq!(new.index, old.index);
+        assert_ne!(new, old);
+        assert!(graph.get(old).is_none());
+        assert!(graph.get_mut(old).is_none());
+        assert_eq!(graph[new].name, "new");
+    }
+
+    #[test]
+    #[should_panic(expected = "Stale or invalid NodeId")]
+    fn indexing_with_a_stale_id_panics() {
+        let mut graph = SceneGraph::new();
+        let old = named(&mut graph, "old");
+        graph.remove(old);
+        named(&mut graph, "new");
+        let _ = &graph[old];
+    }
+
+    // root
+    //   helicopter_1: main_rotor, tail/tail_rotor
+    //   helicopter_2: main_rotor, tail/tail_rotor
+    //   helicopter_10
+    //   terrain
+    fn fleet() -> (SceneGraph, NodeId) {
+        let mut graph = SceneGraph::new();
+        let root = named(&mut graph, "root");
+        for name in ["helicopter_1", "helicopter_2"] {
+            let helicopter = named(&mut graph, name);
+            graph.add_child(root, helicopter);
+            let main_rotor = named(&mut graph, "main_rotor");
+            graph.add_child(helicopter, main_rotor);
+            let tail = named(&mut graph, "tail");
+            graph.add_child(helicopter, tail);
+            let tail_rotor = named(&mut graph, "tail_rotor");
+            graph.add_child(tail, tail_rotor);
+        }
+        for name in ["helicopter_10", "terrain"] {
+            let node = named(&mut graph, name);
+            graph.add_child(root, node);
+        }
+        (graph, root)
+    }
+
+    // The path of each node from below the root
+    fn names(graph: &SceneGraph, nodes: &[NodeId]) -> Vec<String> {
+        let path = |&node: &NodeId| {
+            let mut names = vec![];
+            let mut current = Some(node);
+            while let Some(id) = current.filter(|&id| graph[id].parent().is_some()) {
+                names.push(graph[id].name.as_str());
+                current = graph[id].parent();
+            }
+            names.reverse();
+            names.join("/")
+        };
+        nodes.iter().map(path).collect()
+    }
+
+    #[test]
+    fn find_by_path() {
+        let (graph, root) = fleet();
+        let tail_rotor = graph.find(root, "helicopter_2/tail/tail_rotor").unwrap();
+        assert_eq!(
+            names(&graph, &[tail_rotor]),
+            ["helicopter_2/tail/tail_rotor"]
+        );
+        // Extra slashes are ignored, and an empty path is the root itself
+        assert_eq!(
+            graph.find(root, "/helicopter_2//tail/tail_rotor/"),
+            Some(tail_rotor)
+        );
+        assert_eq!(graph.find(root, ""), Some(root));
+        assert_eq!(graph.find(root, "helicopter_2/tail_rotor"), None);
+        assert_eq!(graph.find(root, "helicopter_3"), None);
+    }
+
+    #[test]
+    fn glob_queries() {
+        let (graph, root) = fleet();
+        let query = |pattern| names(&graph, &graph.query(root, pattern));
+        assert_eq!(
+            query("helicopter_*"),
+            ["helicopter_1", "helicopter_2", "helicopter_10"]
+        );
+        assert_eq!(query("helicopter_?"), ["helicopter_1", "helicopter_2"]);
+        assert_eq!(
+            query("*/main_rotor"),
+            ["helicopter_1/main_rotor", "helicopter_2/main_rotor"]
+        );
+        // `**` matches any number of levels, none included
+        assert_eq!(
+            query("**/*_rotor"),
+            [
+                "helicopter_1/main_rotor",
+                "helicopter_1/tail/tail_rotor",
+                "helicopter_2/main_rotor",
+                "helicopter_2/tail/tail_rotor",
+            ]
+        );
+        assert_eq!(query("**/terrain"), ["terrain"]);
+        assert_eq!(query("helicopter_1/**").len(), 4);
+        assert!(query("*/rotor").is_empty());
+    }
+
+    #[test]
+    fn glob_segments() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*c", "abbbc"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(glob_match("?_rotor", "a_rotor"));
+        assert!(!glob_match("?_rotor", "_rotor"));
+        assert!(!glob_match("a*c", "abcd"));
+    }
+
+    #[test]
+    fn moving_an_ancestor_moves_its_descendants() {
+        let mut graph = SceneGraph::new();
+        let root = named(&mut graph, "root");
+        let middle = named(&mut graph, "middle");
+        let leaf = named(&mut graph, "leaf");
+        let other_leaf = named(&mut graph, "other_leaf");
+        graph.add_child(root, middle);
+        graph.add_child(middle, leaf);
+        graph.add_child(middle, other_leaf);
+        graph[middle].set_position(glm::vec3(0.0, 1.0, 0.0));
+        graph[leaf].set_position(glm::vec3(1.0, 0.0, 0.0));
+        assert_eq!(graph.world_position(leaf), glm::vec3(1.0, 1.0, 0.0));
+        assert!(!graph[leaf].world_dirty.get());
+
+        // The cached matrices below the root are stale once it moves, even though only the
+        // root was touched
+        graph[root].set_position(glm::vec3(0.0, 0.0, 5.0));
+        assert_eq!(graph.world_position(leaf), glm::vec3(1.0, 1.0, 5.0));
+        assert_eq!(graph.world_position(other_leaf), glm::vec3(0.0, 1.0, 5.0));
+
+        // Asking for the middle first must still leave the leaves to be updated
+        graph[root].set_rotation(glm::vec3(0.0, glm::half_pi(), 0.0));
+        graph.world_matrix(middle);
+        assert!(glm::distance(&graph.world_position(leaf), &glm::vec3(0.0, 1.0, 4.0)) < 1e-5);
+        assert_eq!(
+            graph.world_position(other_leaf),
+            graph.world_position(middle)
+        );
+    }
+
+    fn drawing(graph: &mut SceneGraph, name: &str, vao: u32) -> NodeId {
+        graph.add(SceneNode::from_vao(vao, 3).with_name(name))
+    }
+
+    #[test]
+    fn removing_releases_only_unshared_vaos() {
+        let mut graph = SceneGraph::new();
+        let root = named(&mut graph, "root");
+        let helicopter = drawing(&mut graph, "helicopter", 1);
+        let rotor = drawing(&mut graph, "rotor", 2);
+        let light = drawing(&mut graph, "light", 3);
+        graph.add_child(root, helicopter);
+        graph.add_child(helicopter, rotor);
+        graph.add_child(helicopter, light);
+        let copy = graph.clone_subtree(helicopter);
+        graph.add_child(root, copy);
+        // The copy keeps drawing the body and rotor, but not the light
+        let copied_light = graph.find(copy, "light").unwrap();
+        graph.remove(copied_light);
+
+        let mut released = graph.remove(helicopter);
+        released.sort_unstable();
+        assert_eq!(released, [3]);
+        assert!(!graph.contains(rotor));
+        assert_eq!(graph[root].children(), [copy]);
+
+        let mut released = graph.remove(copy);
+        released.sort_unstable();
+        assert_eq!(released, [1, 2]);
+        assert_eq!(graph.nodes().count(), 1);
+    }
+
+    #[test]
+    fn reparenting_can_keep_the_world_transform() {
+        let mut graph = SceneGraph::new();
+        let (a, b, node) = (
+            named(&mut graph, "a"),
+            named(&mut graph, "b"),
+            named(&mut graph, "node"),
+        );
+        graph[a].set_position(glm::vec3(1.0, 2.0, 3.0));
+        graph[a].set_rotation(glm::vec3(0.3, 1.0, -0.2));
+        graph[b].set_position(glm::vec3(-4.0, 0.5, 2.0));
+        graph[b].set_rotation(glm::vec3(-0.5, 2.0, 0.4));
+        graph[b].set_scale(glm::vec3(2.0, 2.0, 2.0));
+        graph[node].set_position(glm::vec3(0.5, 0.0, -1.0));
+        graph[node].set_rotation(glm::vec3(0.1, 0.2, 0.3));
+        graph.add_child(a, node);
+
+        let before = graph.world_matrix(node);
+        graph.reparent(node, b, true);
+        assert_eq!(graph[node].parent(), Some(b));
+        assert_eq!(graph[a].children(), []);
+        let after = graph.world_matrix(node);
+        assert!(
+            (after - before).abs().max() < 1e-5,
+            "{} != {}",
+            after,
+            before
+        );
+
+        // Without keeping it, the node keeps its place relative to its parent instead
+        let local = graph[node].local_matrix();
+        graph.reparent(node, a, false);
+        assert_eq!(graph[node].local_matrix(), local);
+        assert!((graph.world_matrix(node) - before).abs().max() > 0.1);
+    }
+
+    #[test]
+    fn clones_are_deep_and_independent() {
+        let mut graph = SceneGraph::new();
+        let helicopter = drawing(&mut graph, "helicopter", 1);
+        let tail = named(&mut graph, "tail");
+        let tail_rotor = drawing(&mut graph, "tail_rotor", 2);
+        graph.add_child(helicopter, tail);
+        graph.add_child(tail, tail_rotor);
+        graph[tail].set_position(glm::vec3(0.0, 0.0, 1.0));
+
+        let copy = graph.clone_subtree(helicopter);
+        assert_eq!(graph[copy].parent(), None);
+        let copied_tail = graph.find(copy, "tail").unwrap();
+        let copied_rotor = graph.find(copy, "tail/tail_rotor").unwrap();
+        assert!(![helicopter, tail, tail_rotor].contains(&copied_tail));
+        assert_ne!(copied_rotor, tail_rotor);
+        assert_eq!(graph[copied_rotor].vao_id, 2);
+        assert_eq!(graph[copied_tail].parent(), Some(copy));
+        assert_eq!(graph[copied_tail].position(), glm::vec3(0.0, 0.0, 1.0));
+
+        // Changing or removing the copy leaves the original alone
+        graph[copied_tail].set_position(glm::vec3(5.0, 0.0, 0.0));
+        graph.remove(copied_rotor);
+        assert_eq!(graph[tail].position(), glm::vec3(0.0, 0.0, 1.0));
+        assert_eq!(graph[tail].children(), [tail_rotor]);
+        assert_eq!(graph.world_position(tail_rotor), glm::vec3(0.0, 0.0, 1.0));
     }
 }
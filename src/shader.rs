@@ -20,6 +20,7 @@ pub enum ShaderType {
 
 impl Shader {
     // Make sure the shader is active before calling this
+    #[allow(dead_code)]
     pub unsafe fn get_uniform_location(&self, name: &str) -> i32 {
         let name_cstr = CString::new(name).expect("CString::new failed");
         gl::GetUniformLocation(self.program_id, name_cstr.as_ptr())
@@ -0,0 +1,217 @@
+extern crate nalgebra_glm as glm;
+
+use std::time::Instant;
+
+use crate::rotation;
+use crate::scene_graph::{NodeId, SceneGraph};
+
+// Running the simulation at a fixed rate, whatever the frame rate is.
+//
+// Every frame the time passed on the clock goes into an accumulator, and the simulation is
+// stepped by a fixed timestep for as long as there is a whole step left in it. What remains is
+// less than a step, so nodes are drawn that far in between the last two simulated states.
+
+// How long a single simulation step is, in seconds
+pub const SIMULATION_TIMESTEP: f32 = 1_f32 / 120_f32;
+
+// Never fall further behind than this, or a long stall would be followed by a burst of steps
+// that takes even longer to simulate
+const MAX_FRAME_TIME: f64 = 0.25;
+
+// Where time comes from. Anything that needs to be reproducible, like tests, can use a
+// `SteppedClock` instead of the system clock.
+pub trait Clock {
+    // Seconds since some fixed point in time
+    fn now(&mut self) -> f64;
+}
+
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&mut self) -> f64 {
+        self.start.elapsed().as_secs_f64()
+    }
+}
+
+// A clock that moves forward by the same amount every time it is read
+pub struct SteppedClock {
+    pub time: f64,
+    pub step: f64,
+}
+
+impl SteppedClock {
+    pub fn new(step: f64) -> SteppedClock {
+        SteppedClock { time: 0.0, step }
+    }
+}
+
+impl Clock for SteppedClock {
+    fn now(&mut self) -> f64 {
+        let now = self.time;
+        self.time += self.step;
+        now
+    }
+}
+
+pub struct FixedTimestep {
+    pub timestep: f32,
+    accumulator: f64,
+    previous_frame: Option<f64>,
+}
+
+impl FixedTimestep {
+    pub fn new(timestep: f32) -> FixedTimestep {
+        FixedTimestep {
+            timestep,
+            accumulator: 0.0,
+            previous_frame: None,
+        }
+    }
+
+    // Start a frame at `now` seconds on the clock, and return the time since the last frame along
+    // with how many steps to simulate
+    pub fn advance_to(&mut self, now: f64) -> (f32, usize) {
+        let frame_time = match self.previous_frame {
+            Some(previous) => f64::min(now - previous, MAX_FRAME_TIME),
+            None => 0.0,
+        };
+        self.previous_frame = Some(now);
+
+        self.accumulator += frame_time;
+        let steps = (self.accumulator / self.timestep as f64) as usize;
+        self.accumulator -= steps as f64 * self.timestep as f64;
+        (frame_time as f32, steps)
+    }
+
+    // How far between the last two simulated states the frame is, from 0 to 1
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.timestep as f64) as f32
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Transform {
+    position: glm::Vec3,
+    orientation: glm::Quat,
+    scale: glm::Vec3,
+}
+
+impl Transform {
+    fn read(scene: &SceneGraph, node: NodeId) -> Transform {
+        Transform {
+            position: scene[node].position(),
+            orientation: scene[node].orientation(),
+            scale: scene[node].scale(),
+        }
+    }
+
+    fn write(&self, scene: &mut SceneGraph, node: NodeId) {
+        scene[node].set_position(self.position);
+        scene[node].set_orientation(self.orientation);
+        scene[node].set_scale(self.scale);
+    }
+}
+
+// Remembers the transforms of some nodes in the last two simulated states, so they can be
+// drawn in between. Wrap every simulation step in `begin_step` and `end_step`.
+pub struct NodeInterpolation {
+    nodes: Vec<NodeId>,
+    previous: Vec<Transform>,
+    current: Vec<Transform>,
+}
+
+impl NodeInterpolation {
+    pub fn new(scene: &SceneGraph, nodes: Vec<NodeId>) -> NodeInterpolation {
+        let current: Vec<Transform> = nodes
+            .iter()
+            .map(|&node| Transform::read(scene, node))
+            .collect();
+        NodeInterpolation {
+            nodes,
+            previous: current.clone(),
+            current,
+        }
+    }
+
+    // Put back the simulated state the last frame was drawn from
+    pub fn begin_step(&mut self, scene: &mut SceneGraph) {
+        for (node, transform) in self.nodes.iter().zip(&self.current) {
+            transform.write(scene, *node);
+        }
+        self.previous.clone_from(&self.current);
+    }
+
+    pub fn end_step(&mut self, scene: &SceneGraph) {
+        for (node, transform) in self.nodes.iter().zip(self.current.iter_mut()) {
+            *transform = Transform::read(scene, *node);
+        }
+    }
+
+    // Pose the nodes `alpha` of the way from the previous to the current state
+    pub fn interpolate(&self, scene: &mut SceneGraph, alpha: f32) {
+        for ((node, previous), current) in self.nodes.iter().zip(&self.previous).zip(&self.current)
+        {
+            Transform {
+                position: glm::lerp(&previous.position, &current.position, alpha),
+                orientation: rotation::quat_slerp(
+                    &previous.orientation,
+                    &current.orientation,
+                    alpha,
+                ),
+                scale: glm::lerp(&previous.scale, &current.scale, alpha),
+            }
+            .write(scene, *node);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Steps and frames that are powers of two apart, so no rounding gets in the way
+    const TIMESTEP: f32 = 0.125;
+
+    #[test]
+    fn steps_as_often_as_the_time_passed_allows() {
+        let mut clock = SteppedClock::new(0.1875);
+        let mut timestep = FixedTimestep::new(TIMESTEP);
+        // Nothing has passed before the first frame
+        assert_eq!(timestep.advance_to(clock.now()), (0.0, 0));
+        assert_eq!(timestep.advance_to(clock.now()), (0.1875, 1));
+        assert_eq!(timestep.advance_to(clock.now()), (0.1875, 2));
+        assert_eq!(timestep.advance_to(clock.now()), (0.1875, 1));
+    }
+
+    #[test]
+    fn long_frames_are_clamped() {
+        let mut timestep = FixedTimestep::new(TIMESTEP);
+        timestep.advance_to(0.0);
+        assert_eq!(timestep.advance_to(10.0), (MAX_FRAME_TIME as f32, 2));
+        assert_eq!(timestep.alpha(), 0.0);
+        // The time skipped is gone for good
+        assert_eq!(timestep.advance_to(10.0625), (0.0625, 0));
+    }
+
+    #[test]
+    fn alpha_is_the_time_left_over() {
+        let mut timestep = FixedTimestep::new(TIMESTEP);
+        timestep.advance_to(0.0);
+        timestep.advance_to(0.1875);
+        assert_eq!(timestep.alpha(), 0.5);
+        timestep.advance_to(0.25);
+        assert_eq!(timestep.alpha(), 0.0);
+        timestep.advance_to(0.28125);
+        assert_eq!(timestep.alpha(), 0.25);
+    }
+}
@@ -1,36 +1,305 @@
 extern crate nalgebra_glm as glm;
-use std::f64::consts::PI;
+
+use crate::animation::{AnimationPlayer, Clip};
+use crate::blending::{AnimationLayer, Animator, Blend};
+use crate::input::Actions;
+use crate::path::{CurveKind, Path};
+use crate::scene_graph::{NodeId, SceneGraph};
 
 pub struct Heading {
     pub x: f32,
+    pub y: f32,
     pub z: f32,
     pub roll: f32,  // measured in radians
     pub pitch: f32, // measured in radians
     pub yaw: f32,   // measured in radians
 }
 
-pub fn simple_heading_animation(time: f32) -> Heading {
-    let t = time as f64;
-    let step = 0.05f64;
-    let path_size = 15f64;
-    let circuit_speed = 0.8f64;
+// How much a helicopter pitches forward per unit of speed, and banks per unit of sideways
+// acceleration.
+const PITCH_PER_SPEED: f32 = 0.175 * 0.05;
+const BANK_PER_ACCELERATION: f32 = 0.019;
 
-    let xpos = path_size * (2.0 * (t + 0.0) * circuit_speed).sin();
-    let xpos_next = path_size * (2.0 * (t + step) * circuit_speed).sin();
-    let zpos = 3.0 * path_size * ((t + 0.0) * circuit_speed).cos();
-    let zpos_next = 3.0 * path_size * ((t + step) * circuit_speed).cos();
+// The heading of a helicopter flying with some velocity and acceleration. Yaw and climb follow
+// the velocity, it pitches forward with speed, and it banks into turns by how hard it is
+// pulled sideways.
+pub fn heading_from_motion(
+    position: &glm::Vec3,
+    velocity: &glm::Vec3,
+    acceleration: &glm::Vec3,
+) -> Heading {
+    let speed = glm::length(velocity);
+    if speed < 1e-6 {
+        return Heading {
+            x: position.x,
+            y: position.y,
+            z: position.z,
+            roll: 0.0,
+            pitch: 0.0,
+            yaw: 0.0,
+        };
+    }
 
-    let delta_pos = glm::vec2(xpos_next - xpos, zpos_next - zpos);
+    let forward = velocity / speed;
+    let left = glm::cross(&glm::vec3(0_f32, 1_f32, 0_f32), &forward);
+    let sideways_acceleration = glm::dot(acceleration, &left);
+    Heading {
+        x: position.x,
+        y: position.y,
+        z: position.z,
+        roll: (BANK_PER_ACCELERATION * sideways_acceleration).atan(),
+        pitch: forward.y.clamp(-1.0, 1.0).asin() - PITCH_PER_SPEED * speed,
+        yaw: glm::pi::<f32>() + forward.x.atan2(forward.z),
+    }
+}
 
-    let roll = (t * circuit_speed).cos() * 0.5;
-    let pitch = -0.175 * glm::length(&delta_pos);
-    let yaw = PI + delta_pos.x.atan2(delta_pos.y);
+// The figure eight the helicopters fly around, as a closed path through `points` points on it.
+// Returns the path and the speed to follow it at to go around in the same time.
+pub fn circuit_path(points: usize) -> (Path, f32) {
+    let path_size = 15_f32;
+    let circuit_speed = 0.8_f32;
+    let period = 2_f32 * glm::pi::<f32>() / circuit_speed;
 
-    Heading {
-        x: xpos as f32,
-        z: zpos as f32,
-        roll: roll as f32,
-        pitch: pitch as f32,
-        yaw: yaw as f32,
+    let control_points = (0..points)
+        .map(|i| {
+            let t = period * i as f32 / points as f32;
+            glm::vec3(
+                path_size * (2.0 * t * circuit_speed).sin(),
+                0.0,
+                3.0 * path_size * (t * circuit_speed).cos(),
+            )
+        })
+        .collect();
+    let path = Path::new(CurveKind::CatmullRom, control_points, true).unwrap();
+    let speed = path.length() / period;
+    (path, speed)
+}
+
+// Spins the rotors of the helicopter at `owner` on an additive layer, on top of a layer tilting
+// the main rotor with the `tilt` clip, like hovering or cruising
+pub fn helicopter_animator(
+    scene: &SceneGraph,
+    owner: NodeId,
+    clips: &[Clip],
+    tilt: &str,
+) -> Result<Animator, String> {
+    let find_clip = |name: &str| {
+        clips
+            .iter()
+            .find(|clip| clip.name == name)
+            .ok_or_else(|| format!("The scene has no animation named {}", name))
+    };
+    let mut tilt_layer =
+        AnimationLayer::new("tilt", Blend::Override).with_mask(scene.query(owner, "main_rotor"));
+    tilt_layer.play(AnimationPlayer::new(find_clip(tilt)?, scene, owner));
+
+    let mut spin = AnimationLayer::new("spin", Blend::Additive);
+    for name in ["main_rotor_spin", "tail_rotor_spin"].iter() {
+        spin.add(AnimationPlayer::new(find_clip(name)?, scene, owner), 1_f32);
+    }
+    Ok(Animator::new(vec![tilt_layer, spin]))
+}
+
+// == // Helicopter flight model
+
+// The pilot's inputs. Collective goes from 0 to 1 and sets how hard the main rotor pushes.
+// Cyclic tilts the helicopter forward (x) and to the right (y), and the pedals turn it to the
+// right, all three between -1 and 1.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FlightControls {
+    pub collective: f32,
+    pub cyclic: glm::Vec2,
+    pub pedal: f32,
+}
+
+impl FlightControls {
+    // Fly with the collective_up/down, cyclic_forward/back/left/right and pedal_left/right
+    // actions. Cyclic and pedals spring back when released.
+    pub fn update_from_actions(&mut self, actions: &Actions, delta_time: f32) {
+        // Several bindings held at once don't push any harder
+        let axis = |positive, negative| actions.axis(positive, negative).clamp(-1.0, 1.0);
+        let collective_rate = 0.5_f32;
+        self.collective = (self.collective
+            + axis("collective_up", "collective_down") * collective_rate * delta_time)
+            .clamp(0.0, 1.0);
+        self.cyclic = glm::vec2(
+            axis("cyclic_forward", "cyclic_back"),
+            axis("cyclic_right", "cyclic_left"),
+        );
+        self.pedal = axis("pedal_right", "pedal_left");
+    }
+}
+
+// Everything about the helicopter that does not change in flight. Lengths are in scene units.
+#[derive(Clone, Copy, Debug)]
+pub struct FlightParameters {
+    pub mass: f32,
+    pub gravity: f32,
+    // Main rotor thrust at full collective and rotor speed, in multiples of the weight
+    pub max_thrust: f32,
+    pub drag: f32,
+    // Torques per unit of cyclic and pedal input, and the main rotor torque per unit of
+    // collective that the tail rotor has to fight
+    pub cyclic_torque: f32,
+    pub pedal_torque: f32,
+    pub rotor_torque: f32,
+    pub moment_of_inertia: f32,
+    pub angular_drag: f32,
+    // Pulls the helicopter back to level, since a keyboard is no joystick
+    pub stability: f32,
+    // Main rotor speed in radians per second, and how fast it gets there
+    pub rotor_speed: f32,
+    pub rotor_spin_up: f32,
+    // How much of its speed the rotor loses per unit of collective pulled above the hover
+    // collective, since the engine can not keep up with the extra load
+    pub rotor_droop: f32,
+}
+
+impl Default for FlightParameters {
+    fn default() -> Self {
+        FlightParameters {
+            mass: 1.0,
+            gravity: 1.6, // we are on the moon after all
+            max_thrust: 2.0,
+            drag: 0.6,
+            cyclic_torque: 2.0,
+            pedal_torque: 1.5,
+            rotor_torque: 1.0,
+            moment_of_inertia: 1.0,
+            angular_drag: 2.5,
+            stability: 3.0,
+            rotor_speed: 15.0,
+            rotor_spin_up: 0.5,
+            rotor_droop: 0.4,
+        }
+    }
+}
+
+impl FlightParameters {
+    // The collective at which the helicopter hovers with its rotor at full speed
+    pub fn hover_collective(&self) -> f32 {
+        1_f32 / self.max_thrust
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FlightState {
+    pub position: glm::Vec3,
+    pub velocity: glm::Vec3,
+    pub orientation: glm::Quat,
+    // In the helicopter's own frame, in radians per second
+    pub angular_velocity: glm::Vec3,
+    pub rotor_speed: f32,
+}
+
+// A rigid body helicopter, stepped by the simulation loop
+#[derive(Clone, Debug)]
+pub struct FlightModel {
+    pub parameters: FlightParameters,
+    pub state: FlightState,
+}
+
+impl FlightModel {
+    // Start out hovering with the rotor at full speed
+    pub fn new(position: glm::Vec3, orientation: glm::Quat) -> FlightModel {
+        let parameters = FlightParameters::default();
+        FlightModel {
+            parameters,
+            state: FlightState {
+                position,
+                velocity: glm::zero(),
+                orientation,
+                angular_velocity: glm::zero(),
+                rotor_speed: parameters.rotor_speed,
+            },
+        }
+    }
+
+    // Advance the simulation with semi-implicit Euler integration
+    pub fn step(&mut self, controls: &FlightControls, dt: f32) {
+        let p = &self.parameters;
+        let state = &mut self.state;
+
+        // The rotor slows down under heavy collective and spins back up when it is lowered
+        let collective = controls.collective.clamp(0.0, 1.0);
+        let load = (collective - p.hover_collective()).max(0.0);
+        let governed_speed = p.rotor_speed * (1.0 - p.rotor_droop * load).max(0.0);
+        state.rotor_speed += (governed_speed - state.rotor_speed) * p.rotor_spin_up * dt;
+        // Thrust grows with the square of the rotor speed
+        let rotor_factor = (state.rotor_speed / p.rotor_speed).powi(2);
+
+        let weight = p.mass * p.gravity;
+        let up = glm::quat_rotate_vec3(&state.orientation, &glm::vec3(0_f32, 1_f32, 0_f32));
+        let lift = up * (p.max_thrust * weight * collective * rotor_factor);
+        let gravity = glm::vec3(0_f32, -weight, 0_f32);
+        let drag = -p.drag * state.velocity * glm::length(&state.velocity).max(1.0);
+        state.velocity += (lift + gravity + drag) / p.mass * dt;
+        state.position += state.velocity * dt;
+
+        // The nose points along -Z, so pitching it down is a negative rotation about X, and
+        // rolling or turning to the right is a negative rotation about Z or Y
+        let cyclic = glm::clamp(&controls.cyclic, -1.0, 1.0);
+        let pedal = controls.pedal.clamp(-1.0, 1.0);
+        let yaw_torque = rotor_factor
+            * (-pedal * p.pedal_torque + (collective - p.hover_collective()) * p.rotor_torque);
+        let control_torque = glm::vec3(
+            -cyclic.x * p.cyclic_torque,
+            yaw_torque,
+            -cyclic.y * p.cyclic_torque,
+        );
+        let world_up = glm::quat_rotate_vec3(
+            &glm::quat_inverse(&state.orientation),
+            &glm::vec3(0_f32, 1_f32, 0_f32),
+        );
+        let levelling = glm::cross(&glm::vec3(0_f32, 1_f32, 0_f32), &world_up) * p.stability;
+        let torque = control_torque + levelling - p.angular_drag * state.angular_velocity;
+        state.angular_velocity += torque / p.moment_of_inertia * dt;
+
+        let spin = glm::Quat::new(
+            0_f32,
+            state.angular_velocity.x,
+            state.angular_velocity.y,
+            state.angular_velocity.z,
+        );
+        state.orientation =
+            glm::quat_normalize(&(state.orientation + state.orientation * spin * (0.5 * dt)));
+    }
+
+    // Move the helicopter's node to where the simulation has it
+    pub fn apply(&self, scene: &mut SceneGraph, body: NodeId) {
+        scene[body].set_position(self.state.position);
+        scene[body].set_orientation(self.state.orientation);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::SIMULATION_TIMESTEP;
+
+    fn fly(model: &mut FlightModel, collective: f32, seconds: f32) {
+        let controls = FlightControls {
+            collective,
+            ..FlightControls::default()
+        };
+        for _ in 0..(seconds / SIMULATION_TIMESTEP) as usize {
+            model.step(&controls, SIMULATION_TIMESTEP);
+        }
+    }
+
+    #[test]
+    fn rotor_droops_under_load_and_recovers() {
+        let mut model = FlightModel::new(glm::zero(), glm::quat_identity());
+        let full_speed = model.parameters.rotor_speed;
+        let hover = model.parameters.hover_collective();
+
+        fly(&mut model, hover, 1.0);
+        assert_eq!(model.state.rotor_speed, full_speed);
+
+        fly(&mut model, 1.0, 5.0);
+        assert!(model.state.rotor_speed < 0.9 * full_speed);
+
+        fly(&mut model, hover, 10.0);
+        assert!(model.state.rotor_speed > 0.99 * full_speed);
     }
 }
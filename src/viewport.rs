@@ -0,0 +1,126 @@
+// Splitting the window into several views of the scene.
+//
+// Every view is drawn through its own camera into its own rectangle of the window, with the
+// viewport and scissor set to that rectangle so clearing one view leaves the others alone. The
+// layout decides which views are shown and where, and is worked out again from the window size
+// every frame, so it follows the window when it is resized.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum View {
+    // The camera flown with the keyboard and mouse, which also does the picking
+    Main,
+    // Chasing the player's helicopter
+    Chase,
+    // A top-down map centered on the player's helicopter
+    Map,
+}
+
+// A rectangle of the window in pixels, with the origin in the bottom left like OpenGL
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    pub fn aspect(&self) -> f32 {
+        self.width as f32 / i32::max(self.height, 1) as f32
+    }
+
+    // Draw into this rectangle only
+    pub unsafe fn apply(&self) {
+        gl::Viewport(self.x, self.y, self.width, self.height);
+        gl::Scissor(self.x, self.y, self.width, self.height);
+    }
+
+    // Map a cursor position in window coordinates (origin top left) inside this rectangle to
+    // where it would be if the rectangle filled the whole window, or None if it is outside
+    pub fn stretch_cursor(&self, cursor: (u32, u32), window: (u32, u32)) -> Option<(u32, u32)> {
+        let x = cursor.0 as i32 - self.x;
+        let y = cursor.1 as i32 - (window.1 as i32 - self.y - self.height);
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return None;
+        }
+        Some((
+            (x as i64 * window.0 as i64 / self.width as i64) as u32,
+            (y as i64 * window.1 as i64 / self.height as i64) as u32,
+        ))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    // The main view fills the window
+    Single,
+    // The main view on the left, with the chase view and map stacked on the right
+    Split,
+    // The main view fills the window, with the map in the top right corner
+    Inset,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 3] = [Layout::Single, Layout::Split, Layout::Inset];
+
+    // The views to draw in a window of this size, in the order to draw them
+    pub fn views(self, width: u32, height: u32) -> Vec<(View, Rect)> {
+        let (width, height) = (width as i32, height as i32);
+        let full = Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+        match self {
+            Layout::Single => vec![(View::Main, full)],
+            Layout::Split => {
+                let left = width * 2 / 3;
+                let top = height / 2;
+                vec![
+                    (
+                        View::Main,
+                        Rect {
+                            width: left,
+                            ..full
+                        },
+                    ),
+                    (
+                        View::Chase,
+                        Rect {
+                            x: left,
+                            y: top,
+                            width: width - left,
+                            height: height - top,
+                        },
+                    ),
+                    (
+                        View::Map,
+                        Rect {
+                            x: left,
+                            y: 0,
+                            width: width - left,
+                            height: top,
+                        },
+                    ),
+                ]
+            }
+            Layout::Inset => {
+                let size = i32::min(width, height) / 3;
+                let margin = i32::min(10, size / 4);
+                vec![
+                    (View::Main, full),
+                    (
+                        View::Map,
+                        Rect {
+                            x: width - size - margin,
+                            y: height - size - margin,
+                            width: size,
+                            height: size,
+                        },
+                    ),
+                ]
+            }
+        }
+    }
+}
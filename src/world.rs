@@ -0,0 +1,197 @@
+extern crate nalgebra_glm as glm;
+
+use crate::animation::{AnimationPlayer, Clip};
+use crate::blending::Animator;
+use crate::collision::{self, GroundState, TerrainCollider};
+use crate::flocking::{Boid, Flock, Leader};
+use crate::input::Actions;
+use crate::path::Path;
+use crate::scene_graph::{NodeId, SceneGraph};
+use crate::simulation::{FixedTimestep, NodeInterpolation, SIMULATION_TIMESTEP};
+use crate::toolbox::{self, FlightControls, FlightModel};
+
+// Everything in the scene that moves by itself or is flown, stepped at the fixed rate of the
+// simulation.
+//
+// The player's helicopter is flown with the flight model and lands on the terrain, and the other
+// helicopters fly in formation behind a leader going around the circuit, each keeping the place
+// the scene file put it in relative to the leader. Every helicopter spins its rotors on an
+// additive layer on top of a layer tilting the main rotor between hovering and cruising.
+//
+// The render loop and the recording tests both go through `update`, so a replay is tested with
+// the same steps as the window takes.
+
+pub struct World {
+    pub helicopter: NodeId,
+    pub terrain: TerrainCollider,
+    pub flight_controls: FlightControls,
+    pub flight_model: FlightModel,
+    pub landed: bool,
+    flock: Flock,
+    circuit: Path,
+    circuit_speed: f32,
+    clips: Vec<Clip>,
+    animators: Vec<(NodeId, Animator)>,
+    // How fast the rotors spin, 1 being normal
+    rotor_speed: f32,
+    timestep: FixedTimestep,
+    interpolation: NodeInterpolation,
+    time: f32,
+}
+
+impl World {
+    // Fly the player and helicopter_* nodes below `root`, animating them with `clips`
+    pub fn new(
+        scene: &SceneGraph,
+        root: NodeId,
+        terrain: TerrainCollider,
+        clips: Vec<Clip>,
+        rotor_speed: f32,
+    ) -> Result<World, String> {
+        let helicopter = scene
+            .find(root, "player")
+            .ok_or("The scene has no node named player")?;
+
+        let mut flight_controls = FlightControls::default();
+        let flight_model = FlightModel::new(
+            scene[helicopter].position(),
+            scene[helicopter].orientation(),
+        );
+        flight_controls.collective = flight_model.parameters.hover_collective();
+
+        // The player's main rotor tilts between these two while flying
+        for name in ["hover", "cruise"].iter() {
+            if !clips.iter().any(|clip| clip.name == *name) {
+                return Err(format!("The scene has no animation named {}", name));
+            }
+        }
+
+        let (circuit, circuit_speed) = toolbox::circuit_path(24);
+        let mut flock = Flock::new(
+            scene
+                .query(root, "helicopter_*")
+                .into_iter()
+                .map(|h| Boid::new(h, glm::zero(), Some(100_f32 * scene[h].position())))
+                .collect(),
+        );
+        // The circuit turns too tightly for the whole formation to swing around with the leader
+        flock.parameters.turn_with_leader = false;
+
+        let mut rotor_owners = scene.query(root, "helicopter_*");
+        rotor_owners.push(helicopter);
+        let animators = rotor_owners
+            .into_iter()
+            .map(|owner| {
+                let tilt = if owner == helicopter {
+                    "hover"
+                } else {
+                    "cruise"
+                };
+                let mut animator = toolbox::helicopter_animator(scene, owner, &clips, tilt)?;
+                animator.layer_mut("spin").unwrap().set_speed(rotor_speed);
+                Ok((owner, animator))
+            })
+            .collect::<Result<Vec<(NodeId, Animator)>, String>>()?;
+
+        let mut world = World {
+            helicopter,
+            terrain,
+            flight_controls,
+            flight_model,
+            landed: false,
+            flock,
+            circuit,
+            circuit_speed,
+            clips,
+            animators,
+            rotor_speed,
+            timestep: FixedTimestep::new(SIMULATION_TIMESTEP),
+            interpolation: NodeInterpolation::new(scene, scene.query(root, "**")),
+            time: 0_f32,
+        };
+        world.flock.form_up(&world.circuit_leader());
+        Ok(world)
+    }
+
+    // Start a frame at `now` seconds on the clock, and simulate as many steps as the time
+    // passed since the last frame is worth with this frame's actions. Everything is left in
+    // between the last two steps, and the time passed is returned.
+    pub fn update(&mut self, scene: &mut SceneGraph, actions: &Actions, now: f64) -> f32 {
+        let (delta_time, steps) = self.timestep.advance_to(now);
+        for _ in 0..steps {
+            self.interpolation.begin_step(scene);
+            self.step(scene, actions, self.timestep.timestep);
+            self.interpolation.end_step(scene);
+        }
+        self.interpolation.interpolate(scene, self.timestep.alpha());
+        delta_time
+    }
+
+    // Where the leader of the formation is on the circuit now
+    fn circuit_leader(&self) -> Leader {
+        let distance = self.time * self.circuit_speed;
+        Leader {
+            position: self.circuit.point_at_distance(distance),
+            velocity: self
+                .circuit
+                .velocity_at_distance(distance, self.circuit_speed),
+        }
+    }
+
+    fn step(&mut self, scene: &mut SceneGraph, actions: &Actions, dt: f32) {
+        let leader = self.circuit_leader();
+        self.flock.step(&leader, dt);
+        self.flock.apply(scene, 1_f32 / 100_f32);
+        for boid in self.flock.boids.iter_mut() {
+            if self.terrain.clamp_node(scene, boid.node).is_some() {
+                boid.position.y = 100_f32 * scene[boid.node].position().y;
+                boid.velocity.y = f32::max(boid.velocity.y, 0_f32);
+            }
+        }
+
+        // Fly the player's helicopter
+        let helicopter = self.helicopter;
+        self.flight_controls.update_from_actions(actions, dt);
+        self.flight_model.step(&self.flight_controls, dt);
+        self.flight_model.apply(scene, helicopter);
+
+        // Slide along the ground, or touch down on it
+        let contact = self.terrain.node_contact(scene, helicopter);
+        let state = &mut self.flight_model.state;
+        let ground_state = collision::resolve(contact, &mut state.position, &mut state.velocity);
+        if ground_state == GroundState::Landed {
+            state.angular_velocity = glm::zero();
+            if !self.landed {
+                println!("Touched down");
+            }
+        }
+        self.landed = ground_state == GroundState::Landed;
+        self.flight_model.apply(scene, helicopter);
+
+        // The player's rotor tilts forward while the cyclic is pushed, and spins as fast as the
+        // flight model says
+        let tilt_clip = if self.flight_controls.cyclic != glm::vec2(0_f32, 0_f32) {
+            "cruise"
+        } else {
+            "hover"
+        };
+        let rotor_speed = self.rotor_speed * self.flight_model.state.rotor_speed
+            / self.flight_model.parameters.rotor_speed;
+        for (owner, animator) in self.animators.iter_mut() {
+            if *owner == helicopter {
+                let tilt = animator.layer_mut("tilt").unwrap();
+                if tilt.current_clip() != Some(tilt_clip) {
+                    // Both clips were checked for in `new`
+                    let clip = self.clips.iter().find(|clip| clip.name == tilt_clip);
+                    let player = AnimationPlayer::new(clip.unwrap(), scene, *owner);
+                    tilt.crossfade(player, 0.5_f32);
+                }
+                animator.layer_mut("spin").unwrap().set_speed(rotor_speed);
+            }
+            animator.advance(dt);
+            animator.apply(scene);
+        }
+
+        self.time += dt;
+    }
+}